@@ -0,0 +1,682 @@
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    frame_irq: bool,
+    // pulse timers are clocked every other cpu cycle
+    odd_cycle: bool,
+}
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// In cpu cycles
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// In cpu cycles
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::default(),
+            frame_irq: false,
+            odd_cycle: false,
+        }
+    }
+
+    /// Advance the APU by one cpu cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        let step = self.frame_counter.tick();
+        if step.quarter_frame {
+            self.clock_quarter_frame();
+        }
+        if step.half_frame {
+            self.clock_half_frame();
+        }
+        if step.irq && !self.frame_counter.irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    /// The mixed output of all channels, from 0.0 to 1.0
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let triangle = self.triangle.output() as f32;
+        let noise = self.noise.output() as f32;
+        let dmc = self.dmc.output as f32;
+
+        // the mixer is nonlinear, approximated by these formulas
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    /// The address of the next dmc sample byte, if the sample buffer needs to be refilled
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        if self.dmc.sample_buffer.is_none() && self.dmc.bytes_remaining > 0 {
+            Some(self.dmc.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Fill the dmc sample buffer with the byte read from `dmc_fetch_address`
+    pub fn dmc_fill(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    /// Envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    /// Length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.length.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.length.clock();
+        self.pulse_2.clock_sweep();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    /// State of the APU's irq output, from the frame counter or the dmc
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Read $4015
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        status |= (self.pulse_1.length.value > 0) as u8;
+        status |= ((self.pulse_2.length.value > 0) as u8) << 1;
+        status |= ((self.triangle.length.value > 0) as u8) << 2;
+        status |= ((self.noise.length.value > 0) as u8) << 3;
+        status |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
+        status |= (self.frame_irq as u8) << 6;
+        status |= (self.dmc.irq as u8) << 7;
+
+        // reading clears the frame interrupt
+        self.frame_irq = false;
+
+        status
+    }
+
+    /// Write to one of $4000-$4013, $4015 or $4017
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write_register(addr - 0x4000, value),
+            0x4004..=0x4007 => self.pulse_2.write_register(addr - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, value),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write_register(addr - 0x4010, value),
+            // channel enable
+            0x4015 => {
+                self.pulse_1.length.set_enabled(value & 1 << 0 != 0);
+                self.pulse_2.length.set_enabled(value & 1 << 1 != 0);
+                self.triangle.length.set_enabled(value & 1 << 2 != 0);
+                self.noise.length.set_enabled(value & 1 << 3 != 0);
+                self.dmc.set_enabled(value & 1 << 4 != 0);
+            }
+            // frame counter
+            0x4017 => {
+                self.frame_counter.five_step = value & 1 << 7 != 0;
+                self.frame_counter.irq_inhibit = value & 1 << 6 != 0;
+                self.frame_counter.cycle = 0;
+
+                if self.frame_counter.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                // the 5-step sequence clocks everything immediately
+                if self.frame_counter.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => panic!("Write to invalid APU register {:x}", addr),
+        }
+    }
+}
+
+/// Divides the cpu clock into quarter and half frame clocks for the channels
+#[derive(Default)]
+struct FrameCounter {
+    cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+}
+
+#[derive(Default)]
+struct FrameStep {
+    quarter_frame: bool,
+    half_frame: bool,
+    irq: bool,
+}
+
+impl FrameCounter {
+    fn tick(&mut self) -> FrameStep {
+        self.cycle += 1;
+
+        let mut step = FrameStep::default();
+        match (self.cycle, self.five_step) {
+            (7457, _) | (22371, _) => step.quarter_frame = true,
+            (14913, _) => {
+                step.quarter_frame = true;
+                step.half_frame = true;
+            }
+            (29829, false) => {
+                step.quarter_frame = true;
+                step.half_frame = true;
+                step.irq = true;
+            }
+            (29830, false) => self.cycle = 0,
+            (37281, true) => {
+                step.quarter_frame = true;
+                step.half_frame = true;
+            }
+            (37282, true) => self.cycle = 0,
+            _ => (),
+        }
+
+        step
+    }
+}
+
+/// Silences a channel after a set number of half frames
+#[derive(Default)]
+struct LengthCounter {
+    value: u8,
+    enabled: bool,
+    halt: bool,
+}
+
+impl LengthCounter {
+    fn clock(&mut self) {
+        if !self.halt && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[index as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+}
+
+/// Volume control for the pulse and noise channels, either constant or a decaying sawtooth
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    // volume when constant, otherwise the period of the divider
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looping = value & 1 << 5 != 0;
+        self.constant_volume = value & 1 << 4 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+struct Pulse {
+    // pulse 1 negates its sweep with ones' complement, pulse 2 with two's complement
+    ones_complement: bool,
+    duty: u8,
+    sequence: u8,
+    timer: u16,
+    period: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            duty: 0,
+            sequence: 0,
+            timer: 0,
+            period: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halt = value & 1 << 5 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 1 << 7 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 1 << 3 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length.load(value >> 3);
+                // restart the sequence and envelope
+                self.sequence = 0;
+                self.envelope.start = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// The period the sweep unit is moving towards
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = change + self.ones_complement as u16;
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+            || self.length.value == 0
+            || self.muted()
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    sequence: u8,
+    timer: u16,
+    period: u16,
+    length: LengthCounter,
+    // also halts the length counter
+    linear_control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.linear_control = value & 1 << 7 != 0;
+                self.length.halt = self.linear_control;
+                self.linear_reload_value = value & 0x7F;
+            }
+            1 => (), // unused
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length.load(value >> 3);
+                self.linear_reload = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // the sequencer only runs while both counters are non-zero
+            if self.length.value > 0 && self.linear_counter > 0 {
+                self.sequence = (self.sequence + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.linear_control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        // the triangle isn't silenced, it holds its current output when halted
+        TRIANGLE_TABLE[self.sequence as usize]
+    }
+}
+
+struct Noise {
+    // 15-bit linear feedback shift register
+    shift: u16,
+    // use bit 6 instead of bit 1 for feedback, giving a shorter, more metallic sequence
+    short_mode: bool,
+    timer: u16,
+    period: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self {
+            shift: 1,
+            short_mode: false,
+            timer: 0,
+            period: NOISE_PERIOD_TABLE[0],
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.halt = value & 1 << 5 != 0;
+                self.envelope.write(value);
+            }
+            1 => (), // unused
+            2 => {
+                self.short_mode = value & 1 << 7 != 0;
+                self.period = NOISE_PERIOD_TABLE[value as usize & 0x0F];
+            }
+            3 => {
+                self.length.load(value >> 3);
+                self.envelope.start = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+
+            let other_bit = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> other_bit) & 1);
+            self.shift >>= 1;
+            self.shift |= feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || self.length.value == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// Delta modulation channel, which plays 1-bit delta encoded samples read from cpu memory
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    timer: u16,
+    period: u16,
+    // 7-bit output level, also set directly through $4011
+    output: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer: 0,
+            period: DMC_RATE_TABLE[0],
+            output: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 1 << 7 != 0;
+                self.looping = value & 1 << 6 != 0;
+                self.period = DMC_RATE_TABLE[value as usize & 0x0F];
+
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            // direct load
+            1 => self.output = value & 0x7F,
+            // samples start at $C000 + A * 64
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            // and are L * 16 + 1 bytes long
+            3 => self.sample_length = value as u16 * 16 + 1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        // writing to $4015 always clears the dmc interrupt
+        self.irq = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fill(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+
+        // the address wraps around to $8000
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        // each bit moves the output level up or down by 2, if it stays within 0-127
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            // start the next byte, or go silent if the sample buffer wasn't filled in time
+            if let Some(sample) = self.sample_buffer.take() {
+                self.shift = sample;
+                self.silence = false;
+            } else {
+                self.silence = true;
+            }
+        }
+    }
+}
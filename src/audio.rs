@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Receives the console's audio, resampled to `sample_rate`
+pub trait AudioSink {
+    /// Output sample rate in hz, usually 44100 or 48000
+    fn sample_rate(&self) -> u32;
+
+    /// Receive one mono sample, from -1.0 to 1.0
+    fn push_sample(&mut self, sample: f32);
+
+    /// Flush any buffered output, returning the first error hit while writing samples
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes samples to a 16-bit mono PCM wav file
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    samples_written: u32,
+    // the first error hit while writing samples, returned by `finish`
+    error: Option<io::Error>,
+}
+
+const WAV_HEADER_LEN: u32 = 44;
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        let mut wav = Self {
+            writer: BufWriter::new(File::create(path)?),
+            sample_rate,
+            samples_written: 0,
+            error: None,
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_len = self.samples_written * 2;
+
+        self.writer.seek(SeekFrom::Start(0))?;
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?; // fmt chunk length
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&1u16.to_le_bytes())?; // mono
+        w.write_all(&self.sample_rate.to_le_bytes())?;
+        w.write_all(&(self.sample_rate * 2).to_le_bytes())?; // bytes per second
+        w.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        w.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+        w.write_all(b"data")?;
+        w.write_all(&data_len.to_le_bytes())?;
+
+        self.writer.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_sample(&mut self, sample: f32) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        match self.writer.write_all(&value.to_le_bytes()) {
+            Ok(()) => self.samples_written += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Fill in the header with the final length of the file and flush it to disk
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        // best effort, call `finish` to see errors
+        if self.error.is_none() {
+            let _ = self.write_header();
+            let _ = self.writer.flush();
+        }
+    }
+}
+
+/// A fixed size queue of samples for a frontend to play. Clones share the same buffer, so one
+/// can be given to the console and another to an audio callback on a different thread
+#[derive(Clone)]
+pub struct RingBuffer {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    capacity: usize,
+    sample_rate: u32,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize, sample_rate: u32) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            sample_rate,
+        }
+    }
+
+    /// Take the oldest sample, if there is one
+    pub fn pop(&self) -> Option<f32> {
+        self.samples.lock().unwrap().pop_front()
+    }
+
+    /// Fill `out` with the oldest samples, returning how many were available
+    pub fn read(&self, out: &mut [f32]) -> usize {
+        let mut samples = self.samples.lock().unwrap();
+        let n = out.len().min(samples.len());
+        for (dst, src) in out.iter_mut().zip(samples.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AudioSink for RingBuffer {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_sample(&mut self, sample: f32) {
+        let mut samples = self.samples.lock().unwrap();
+        // if the frontend falls behind, drop the oldest samples
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+}
+
+/// Downsamples the APU output from the cpu clock rate to the rate of an `AudioSink`
+pub struct Resampler {
+    sink: Box<dyn AudioSink>,
+    cycles_per_sample: f64,
+    cycles: f64,
+    sum: f32,
+    count: u32,
+    // state of the high pass filter, removing the DC offset like the NES's own output filter
+    previous_input: f32,
+    previous_output: f32,
+    filter_alpha: f32,
+}
+
+impl Resampler {
+    pub fn new(sink: Box<dyn AudioSink>, cpu_clock_rate: f64) -> Self {
+        let sample_rate = sink.sample_rate() as f64;
+
+        // 90hz first order high pass
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * 90.0);
+        let dt = 1.0 / sample_rate;
+
+        Self {
+            sink,
+            cycles_per_sample: cpu_clock_rate / sample_rate,
+            cycles: 0.0,
+            sum: 0.0,
+            count: 0,
+            previous_input: 0.0,
+            previous_output: 0.0,
+            filter_alpha: (rc / (rc + dt)) as f32,
+        }
+    }
+
+    /// Stop resampling and give back the sink
+    pub fn into_sink(self) -> Box<dyn AudioSink> {
+        self.sink
+    }
+
+    /// Take the APU output for one cpu cycle, passing on a sample when enough have been taken
+    pub fn push(&mut self, value: f32) {
+        self.sum += value;
+        self.count += 1;
+        self.cycles += 1.0;
+
+        if self.cycles >= self.cycles_per_sample {
+            self.cycles -= self.cycles_per_sample;
+
+            // average the cycles that make up this sample
+            let input = self.sum / self.count as f32;
+            self.sum = 0.0;
+            self.count = 0;
+
+            let output = self.filter_alpha * (self.previous_output + input - self.previous_input);
+            self.previous_input = input;
+            self.previous_output = output;
+
+            // the APU's output peaks at around 1.0, so this stays within -1.0 to 1.0
+            self.sink.push_sample(output);
+        }
+    }
+}
@@ -1,23 +1,119 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::{cpu::*, ppu::Ppu, ppu::PpuRegister};
+use crate::{
+    apu::Apu,
+    console::Region,
+    controller::{ButtonState, Controller},
+    cpu::*,
+    mapper::Mapper,
+    ppu::Ppu,
+    ppu::PpuRegister,
+};
 
 pub struct Bus {
     ram: [u8; 0x0800],
-    program_rom: [u8; 0x8000],
+    cartridge: Rc<RefCell<dyn Mapper>>,
     ppu: Rc<RefCell<Ppu>>,
+    apu: Rc<RefCell<Apu>>,
+    controllers: [Controller; 2],
+    oam_dma: bool,
+    region: Region,
+    // cycles into the current instruction, counted by bus accesses, and how many of those the
+    // PPU has been run for
+    cycle: usize,
+    ppu_cycles: usize,
+    // PPU dots owed to the PPU but not yet run, in fifths of a dot
+    pending_dots: usize,
 }
 
 impl Bus {
-    pub fn new(program_rom: [u8; 0x8000], ppu: Rc<RefCell<Ppu>>) -> Self {
+    pub fn new(
+        cartridge: Rc<RefCell<dyn Mapper>>,
+        ppu: Rc<RefCell<Ppu>>,
+        apu: Rc<RefCell<Apu>>,
+        region: Region,
+    ) -> Self {
         Self {
             ram: [0; 0x0800],
-            program_rom,
+            cartridge,
             ppu,
+            apu,
+            controllers: Default::default(),
+            oam_dma: false,
+            region,
+            cycle: 0,
+            ppu_cycles: 0,
+            pending_dots: 0,
         }
     }
 
+    /// Start counting the cycles of the next instruction
+    pub fn begin_instruction(&mut self) {
+        self.cycle = 0;
+        self.ppu_cycles = 0;
+    }
+
+    /// Run the PPU for the rest of an instruction that took `cycles` cycles
+    pub fn finish_instruction(&mut self, cycles: usize) {
+        self.run_ppu(cycles.saturating_sub(self.ppu_cycles));
+        self.ppu_cycles = cycles;
+    }
+
+    /// Run the PPU up to the cycle the current access happens in, so PPU registers are read
+    /// and written at the right dot rather than where the instruction started
+    fn catch_up_ppu(&mut self) {
+        let cycles = self.cycle.saturating_sub(1);
+        if cycles > self.ppu_cycles {
+            self.run_ppu(cycles - self.ppu_cycles);
+            self.ppu_cycles = cycles;
+        }
+    }
+
+    fn run_ppu(&mut self, cycles: usize) {
+        self.pending_dots += cycles * self.region.dots_per_cycle();
+
+        let mut ppu = self.ppu.borrow_mut();
+        while self.pending_dots >= 5 {
+            ppu.tick();
+            self.pending_dots -= 5;
+        }
+    }
+
+    /// Set the buttons held on the controller in `port` (0 or 1), other ports are ignored
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        if let Some(controller) = self.controllers.get_mut(port) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    /// Read the next dmc sample byte if the apu needs one, returning the number of cycles the
+    /// cpu is halted for
+    pub fn service_dmc(&mut self) -> usize {
+        let request = self.apu.borrow().dmc_fetch_address();
+        if let Some(addr) = request {
+            let value = self.read(MemLocation(addr));
+            self.apu.borrow_mut().dmc_fill(value);
+            4
+        } else {
+            0
+        }
+    }
+
+    /// Returns true once after each oam dma, which the cpu needs to be stalled for
+    pub fn poll_oam_dma(&mut self) -> bool {
+        let oam_dma = self.oam_dma;
+        self.oam_dma = false;
+        oam_dma
+    }
+
+    /// State of the shared irq line, which any of the devices can hold low
+    pub fn irq(&self) -> bool {
+        self.apu.borrow().irq() || self.cartridge.borrow().irq()
+    }
+
     pub fn read(&mut self, addr: MemLocation) -> u8 {
+        self.cycle += 1;
+
         match addr.0 {
             // internal ram
             0..=0x07FF => self.ram[addr.0 as usize],
@@ -29,20 +125,23 @@ impl Bus {
             0x2000..=0x2007 => self.handle_ppu_read(addr.0),
             // mirror PPU registers
             0x2008..=0x3FFF => 0,
-            // APU registers
+            // APU status
+            0x4015 => self.apu.borrow_mut().read_status(),
+            // controllers
+            0x4016 => self.controllers[0].read(),
+            0x4017 => self.controllers[1].read(),
+            // APU registers, not readable
             0x4000..=0x4017 => 0,
             // Not normally used
             0x4018..=0x401F => 0,
-            // cartridge dependant
-            0x4020..=0x5FFF => 0,
-            // usually cartridge ram, when present
-            0x6000..=0x7FFF => 0,
-            // usally cartridge rom
-            0x8000..=0xFFFF => self.program_rom[addr.0 as usize - 0x8000],
+            // cartridge space: expansion, usually ram at $6000, usually rom at $8000
+            0x4020..=0xFFFF => self.cartridge.borrow_mut().cpu_read(addr.0),
         }
     }
 
     fn handle_ppu_read(&mut self, addr: u16) -> u8 {
+        self.catch_up_ppu();
+
         match addr {
             // ppuctrl
             0x2000 => 0, // not readable
@@ -60,12 +159,13 @@ impl Bus {
             0x2006 => 0, // not readable
             // ppudata
             0x2007 => self.ppu.borrow_mut().read_register(PpuRegister::PpuData),
-            // FIXME: add oamdata at 0x4014
             _ => unreachable!(),
         }
     }
 
     pub fn write(&mut self, addr: MemLocation, value: u8) {
+        self.cycle += 1;
+
         match addr.0 {
             // internal ram
             0..=0x07FF => self.ram[addr.0 as usize] = value,
@@ -77,18 +177,27 @@ impl Bus {
             0x2000..=0x2007 => self.handle_ppu_write(addr.0, value),
             // ppu register mirrors
             0x2008..=0x3FFF => self.handle_ppu_write((addr.0 % 8) + 0x2000, value),
-            // unimplemented
-            0x4000..=0x401F => (),
-            // cartridge dependant
-            0x4020..=0x5FFF => (),
-            // usually cartridge ram, when present
-            0x6000..=0x7FFF => (),
-            // usally cartridge rom
-            0x8000..=0xFFFF => (),
+            // APU registers
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.apu.borrow_mut().write_register(addr.0, value)
+            }
+            // oam dma
+            0x4014 => self.oam_dma(value),
+            // controller strobe
+            0x4016 => {
+                self.controllers[0].write(value);
+                self.controllers[1].write(value);
+            }
+            // Not normally used
+            0x4018..=0x401F => (),
+            // cartridge space: expansion, usually ram at $6000, usually rom at $8000
+            0x4020..=0xFFFF => self.cartridge.borrow_mut().cpu_write(addr.0, value),
         }
     }
 
     fn handle_ppu_write(&mut self, addr: u16, value: u8) {
+        self.catch_up_ppu();
+
         match addr {
             // ppuctrl
             0x2000 => self
@@ -127,10 +236,26 @@ impl Bus {
                 .ppu
                 .borrow_mut()
                 .write_register(PpuRegister::PpuData, value),
-            // FIXME: add oamdata at 0x4014
             _ => unreachable!(),
         }
     }
+
+    /// Copy a page of cpu memory to oam, starting at oamaddr
+    fn oam_dma(&mut self, page: u8) {
+        // the copy happens after the instruction, so its reads aren't counted as its cycles
+        let cycle = self.cycle;
+
+        let start = (page as u16) << 8;
+        for offset in 0..256 {
+            let value = self.read(MemLocation(start + offset));
+            self.ppu
+                .borrow_mut()
+                .write_register(PpuRegister::OamData, value);
+        }
+
+        self.cycle = cycle;
+        self.oam_dma = true;
+    }
 }
 
 /// Stores a big-endian 16-bit memory address
@@ -1,37 +1,268 @@
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::rc::Rc;
 
-use crate::{bus::Bus, cpu::Cpu, ppu::Ppu};
+use crate::{
+    apu::Apu,
+    audio::{AudioSink, Resampler},
+    bus::Bus,
+    controller::ButtonState,
+    cpu::Cpu,
+    file::RomHeader,
+    mapper::Mapper,
+    ppu::{Frame, Ppu},
+};
 
 pub struct Console {
     pub cpu: Cpu,
     pub ppu: Rc<RefCell<Ppu>>,
+    pub apu: Rc<RefCell<Apu>>,
+    cartridge: Rc<RefCell<dyn Mapper>>,
+    region: Region,
+    audio: Option<Resampler>,
+    save_path: Option<PathBuf>,
+    // battery backed ram as of the last save, so unchanged ram isn't written again
+    saved_ram: Vec<u8>,
+    last_save_frame: u64,
+    // the error from the last periodic save that failed, until it's taken
+    save_error: Option<io::Error>,
 }
 
+/// How often battery backed ram is flushed to disk, about every 5 seconds
+const SAVE_INTERVAL_FRAMES: u64 = 300;
+
 impl Console {
+    /// Create a console of the region the game was made for
     pub fn new(game: Game) -> Self {
-        let ppu = Rc::new(RefCell::new(Ppu::new(game.character_rom)));
-        let cpu = Cpu::new(Bus::new(game.program_rom, Rc::clone(&ppu)));
-        Self { cpu, ppu }
+        let region = game.region;
+        Self::with_region(game, region)
+    }
+
+    pub fn with_region(game: Game, region: Region) -> Self {
+        // the trainer is copied into PRG ram at $7000 on power up. It's code the game needs to
+        // run, so it replaces any save data loaded into $7000-$71FF
+        if let Some(trainer) = &game.trainer {
+            let mut cartridge = game.cartridge.borrow_mut();
+            if let Some(ram) = cartridge
+                .program_ram()
+                .and_then(|ram| ram.get_mut(0x1000..0x1000 + trainer.len()))
+            {
+                ram.copy_from_slice(trainer);
+            }
+        }
+
+        // taken after the trainer is copied, so a game that never writes to ram never saves
+        let saved_ram = game
+            .cartridge
+            .borrow_mut()
+            .program_ram()
+            .map(|ram| ram.to_vec())
+            .unwrap_or_default();
+
+        let ppu = Rc::new(RefCell::new(Ppu::new(Rc::clone(&game.cartridge), region)));
+        let apu = Rc::new(RefCell::new(Apu::new()));
+        let cpu = Cpu::new(Bus::new(
+            Rc::clone(&game.cartridge),
+            Rc::clone(&ppu),
+            Rc::clone(&apu),
+            region,
+        ));
+        Self {
+            cpu,
+            ppu,
+            apu,
+            cartridge: game.cartridge,
+            region,
+            audio: None,
+            save_path: game.save_path,
+            saved_ram,
+            last_save_frame: 0,
+            save_error: None,
+        }
+    }
+
+    /// Write battery backed ram to the game's save file, if it has changed since the last save
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(save_path) = &self.save_path else {
+            return Ok(());
+        };
+
+        let mut cartridge = self.cartridge.borrow_mut();
+        let Some(ram) = cartridge.program_ram() else {
+            return Ok(());
+        };
+
+        if *ram != *self.saved_ram {
+            fs::write(save_path, &ram)?;
+            self.saved_ram = ram.to_vec();
+        }
+        Ok(())
+    }
+
+    /// Send the APU's output to `sink`, resampled to its sample rate
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = Some(Resampler::new(sink, self.region.cpu_clock_rate()));
+    }
+
+    /// Stop sending audio and give back the sink, so it can be finished and its errors seen
+    pub fn take_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.audio.take().map(Resampler::into_sink)
+    }
+
+    /// Set the buttons held on the controller in `port` (0 or 1), other ports are ignored
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        self.cpu.bus.set_buttons(port, buttons);
+    }
+
+    /// Run one instruction and catch the PPU and APU up to the CPU, returning the number of
+    /// cycles run
+    fn step(&mut self) -> usize {
+        let mut cycles = self.cpu.step();
+
+        let mut cycle = 0;
+        while cycle < cycles {
+            self.apu.borrow_mut().tick();
+            self.cartridge.borrow_mut().cpu_clock();
+
+            if let Some(audio) = &mut self.audio {
+                audio.push(self.apu.borrow().output());
+            }
+
+            // the dmc halts the cpu while it reads sample bytes, which also needs to be caught up
+            let stolen = self.cpu.bus.service_dmc();
+            self.cpu.stall(stolen);
+            cycles += stolen;
+
+            cycle += 1;
+        }
+
+        // the PPU has already been caught up to any register accesses during the instruction
+        self.cpu.bus.finish_instruction(cycles);
+
+        let mut ppu = self.ppu.borrow_mut();
+        if ppu.poll_nmi() {
+            self.cpu.nmi();
+        }
+
+        let frame = ppu.frame_count();
+        drop(ppu);
+
+        // flush saves every few seconds too, in case the emulator isn't closed cleanly
+        if frame >= self.last_save_frame + SAVE_INTERVAL_FRAMES {
+            self.last_save_frame = frame;
+            if let Err(e) = self.save() {
+                self.save_error = Some(e);
+            }
+        }
+
+        cycles
+    }
+
+    /// Take the error from the last periodic save of battery backed ram that failed, if any
+    pub fn take_save_error(&mut self) -> Option<io::Error> {
+        self.save_error.take()
     }
 
     /// Decode and run `n` instructions
-    #[allow(dead_code)]
     pub fn run_steps(&mut self, n: usize) {
         for _ in 0..n {
-            self.cpu.step();
+            self.step();
+        }
+    }
+
+    /// Run for at least `n` CPU cycles, stopping at the end of an instruction
+    pub fn run_cycles(&mut self, n: usize) {
+        let mut cycles = 0;
+        while cycles < n {
+            cycles += self.step();
+        }
+    }
+
+    /// Run until the PPU completes the current frame, then return it. This returns once line 239
+    /// finishes, before vblank begins and before the next frame starts drawing over it
+    pub fn run_frame(&mut self) -> Ref<'_, Frame> {
+        let frame = self.ppu.borrow().frame_count();
+        while self.ppu.borrow().frame_count() == frame {
+            self.step();
         }
+
+        Ref::map(self.ppu.borrow(), |ppu| ppu.frame())
     }
 
-    #[allow(dead_code)]
     pub fn run_continuous(&mut self) {
         loop {
-            self.cpu.step();
+            self.step();
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    /// PPU dots run per CPU cycle, in fifths of a dot (3 for NTSC, 3.2 for PAL)
+    pub fn dots_per_cycle(&self) -> usize {
+        match self {
+            Self::Ntsc => 15,
+            Self::Pal => 16,
+        }
+    }
+
+    /// CPU cycles per second
+    fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Self::Ntsc => 1_789_773.0,
+            Self::Pal => 1_662_607.0,
+        }
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Self::Ntsc => 262,
+            Self::Pal => 312,
+        }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        // best effort, call `save` before dropping to see errors
+        let _ = self.save();
+    }
+}
+
 pub struct Game {
-    pub program_rom: [u8; 0x8000],
-    pub character_rom: [u8; 0x2000], // cartridge ram?
+    pub cartridge: Rc<RefCell<dyn Mapper>>,
+    /// Where battery backed ram is saved, for carts that have a battery
+    pub save_path: Option<PathBuf>,
+    /// 512 bytes for $7000-$71FF, from rom files that have a trainer
+    pub trainer: Option<Vec<u8>>,
+    pub region: Region,
+    pub header: RomHeader,
+}
+
+impl Game {
+    /// Fill battery backed ram from the save file, if there is one yet
+    pub fn load_save(&self) -> io::Result<()> {
+        let Some(save_path) = &self.save_path else {
+            return Ok(());
+        };
+
+        let save = match fs::read(save_path) {
+            Ok(save) => save,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if let Some(ram) = self.cartridge.borrow_mut().program_ram() {
+            let len = ram.len().min(save.len());
+            ram[..len].copy_from_slice(&save[..len]);
+        }
+        Ok(())
+    }
 }
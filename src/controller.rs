@@ -0,0 +1,75 @@
+/// Buttons held on a standard controller
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ButtonState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl ButtonState {
+    /// The buttons in the order they are read out, starting from bit 0
+    fn bits(&self) -> u8 {
+        let buttons = [
+            self.a,
+            self.b,
+            self.select,
+            self.start,
+            self.up,
+            self.down,
+            self.left,
+            self.right,
+        ];
+
+        buttons
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &held)| bits | (held as u8) << i)
+    }
+}
+
+/// A standard controller, read one button at a time through a shift register
+#[derive(Default)]
+pub struct Controller {
+    buttons: ButtonState,
+    // while set, the shift register is continuously reloaded with the button state
+    strobe: bool,
+    shift: u8,
+}
+
+impl Controller {
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits();
+        }
+    }
+
+    /// Write to $4016, which is shared by both controllers
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    /// Read from $4016 or $4017
+    pub fn read(&mut self) -> u8 {
+        let bit = if self.strobe {
+            // always the state of a while strobing
+            self.buttons.a as u8
+        } else {
+            let bit = self.shift & 1;
+            // once all 8 buttons are read the register is filled with 1s
+            self.shift = self.shift >> 1 | 0x80;
+            bit
+        };
+
+        // the upper bits are open bus, which is the high byte of the address
+        0x40 | bit
+    }
+}
@@ -1,5 +1,5 @@
 use crate::bus::{Bus, MemLocation};
-use crate::opcode::{AddrMode, Instruction, Opcode};
+use crate::opcode::{AddrMode, CycleLen, Instruction, Opcode};
 
 pub struct Cpu {
     pub accumulator: u8,
@@ -9,6 +9,17 @@ pub struct Cpu {
     pub status: CpuStatus,
     pub stack_pointer: u8,
     pub bus: Bus,
+    /// Total number of cycles run since power on
+    pub cycles: u64,
+    /// Print each instruction and the registers after it, off by default as it's slow
+    pub trace: bool,
+    // set while running an instruction, used to add the extra cycles from the opcode's CycleLen
+    page_crossed: bool,
+    branch_taken: bool,
+    nmi_pending: bool,
+    // the interrupt disable flag as seen when irqs were last polled, which lags behind the
+    // flag itself after cli, sei and plp
+    irq_masked: bool,
 }
 
 impl Cpu {
@@ -18,26 +29,93 @@ impl Cpu {
             bus.read(MemLocation(0xFFFD)),
         );
 
+        // reset disables irqs
+        let mut status = CpuStatus::new();
+        status.set_int_disable(true);
+
         Cpu {
             accumulator: 0,
             x: 0,
             y: 0,
             pc,
-            status: CpuStatus::new(),
+            status,
             stack_pointer: 0xFD,
             bus,
+            // the reset sequence takes 7 cycles
+            cycles: 7,
+            trace: false,
+            page_crossed: false,
+            branch_taken: false,
+            nmi_pending: false,
+            irq_masked: true,
         }
     }
 
-    pub fn step(&mut self) {
+    /// Signal a non-maskable interrupt, which is serviced before the next instruction
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Halt the cpu for `cycles` cycles while another device uses the bus
+    pub fn stall(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+    }
+
+    /// Decode and run one instruction, returning the number of cycles it took
+    pub fn step(&mut self) -> usize {
+        self.bus.begin_instruction();
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            return self.hardware_interrupt(MemLocation(0xFFFA));
+        }
+
+        // irq is level triggered, so it's serviced for as long as a device holds the line
+        if self.bus.irq() && !self.irq_masked {
+            return self.hardware_interrupt(MemLocation(0xFFFE));
+        }
+
         let code = self.pc_next();
         let opcode = Opcode::decode(code);
         if let Some(opcode) = opcode {
-            println!("{:?} ({:x})", &opcode.0, code);
+            if self.trace {
+                println!("{:?} ({:x})", &opcode.0, code);
+            }
+
+            self.page_crossed = false;
+            self.branch_taken = false;
 
+            let cycle_len = opcode.2.clone();
+            let int_disable = self.status.get_int_disable();
+            let instruction = opcode.0.clone();
             self.run_instruction(opcode);
 
-            println!("{}", self);
+            // irqs are polled before the last cycle, before cli, sei and plp change the flag, so
+            // they only take effect after the next instruction
+            self.irq_masked = match instruction {
+                Instruction::Cli | Instruction::Sei | Instruction::Plp => int_disable,
+                _ => self.status.get_int_disable(),
+            };
+
+            let mut cycles = match cycle_len {
+                CycleLen::Constant(n) => n,
+                // +1 if the indexed address is on a different page than the base address
+                CycleLen::PageCrossed(n) => n + self.page_crossed as usize,
+                // +1 if the branch is taken, +1 more if it lands on a different page
+                CycleLen::Branch => 2 + self.branch_taken as usize + self.page_crossed as usize,
+            };
+
+            // oam dma halts the cpu for 513 cycles, plus 1 if it starts on an odd cycle
+            if self.bus.poll_oam_dma() {
+                cycles += 513 + ((self.cycles + cycles as u64) % 2) as usize;
+            }
+            self.cycles += cycles as u64;
+
+            if self.trace {
+                println!("{}", self);
+            }
+
+            cycles
         } else {
             panic!("Unimplimented opcode {:x}", code);
         }
@@ -74,8 +152,9 @@ impl Cpu {
             Instruction::Bne => self.branch(!self.status.get_zero()),
             Instruction::Bpl => self.branch(!self.status.get_negative()),
             Instruction::Brk => {
-                self.status.set_break(true);
-                self.interrupt(MemLocation(0xFFFE));
+                // skip the padding byte after brk
+                self.pc += 1;
+                self.interrupt(MemLocation(0xFFFE), true);
             }
             Instruction::Bvc => self.branch(!self.status.get_overflow()),
             Instruction::Bvs => self.branch(self.status.get_overflow()),
@@ -139,9 +218,13 @@ impl Cpu {
                 self.or_accumulator(value);
             }
             Instruction::Pha => self.stack_push(self.accumulator),
-            Instruction::Php => self.stack_push(self.status.byte),
+            // the break flag is set in the pushed copy
+            Instruction::Php => self.stack_push(self.status.byte | 1 << 4),
             Instruction::Pla => self.accumulator = self.stack_pop(),
-            Instruction::Plp => self.status.byte = self.stack_pop(),
+            Instruction::Plp => {
+                let status = self.stack_pop();
+                self.pull_status(status);
+            }
             Instruction::Rol => {
                 if addr_mode == AddrMode::Accumulator {
                     self.rotate_left_accumulator();
@@ -189,37 +272,13 @@ impl Cpu {
     }
 
     fn get_value(&mut self, addr_mode: &AddrMode) -> u8 {
-        let mut a = 0;
-        let mut b = 0;
-
-        if addr_mode.n_param_bytes() >= 1 {
-            a = self.pc_next();
-        }
-
-        if addr_mode.n_param_bytes() >= 2 {
-            b = self.pc_next();
-        }
-
         match addr_mode {
-            AddrMode::Immediate => a,
-            AddrMode::ZeroPage => self.memory_read(MemLocation::page_0(a)),
-            AddrMode::ZeroPageX => self.memory_read(MemLocation::page_0(a + self.x)),
-            AddrMode::ZeroPageY => self.memory_read(MemLocation::page_0(a + self.y)),
-            AddrMode::Absolute => self.memory_read(MemLocation::from_little_endian(a, b)),
-            AddrMode::AbsoluteX => self.memory_read(MemLocation::from_little_endian(a, b)),
-            AddrMode::AbsoluteY => self.memory_read(MemLocation::from_little_endian(a, b)),
-            AddrMode::IndirectX => {
-                let location =
-                    MemLocation::page_0(self.memory_read(MemLocation::page_0(a + self.x)));
-                self.memory_read(location)
-            }
-            AddrMode::IndirectY => {
-                let location =
-                    MemLocation::page_0(self.memory_read(MemLocation::page_0(a)) + self.y);
+            AddrMode::Immediate => self.pc_next(),
+            AddrMode::Relative => self.pc_next(),
+            _ => {
+                let location = self.get_location(addr_mode);
                 self.memory_read(location)
             }
-            AddrMode::Relative => a,
-            _ => panic!("Invalid address mode {:?} in get_value", addr_mode),
         }
     }
 
@@ -237,15 +296,34 @@ impl Cpu {
 
         match addr_mode {
             AddrMode::ZeroPage => MemLocation::page_0(a),
-            AddrMode::ZeroPageX => MemLocation::page_0(a + self.x),
-            AddrMode::ZeroPageY => MemLocation::page_0(a + self.y),
+            AddrMode::ZeroPageX => MemLocation::page_0(a.wrapping_add(self.x)),
+            AddrMode::ZeroPageY => MemLocation::page_0(a.wrapping_add(self.y)),
             AddrMode::Absolute => MemLocation::from_little_endian(a, b),
-            AddrMode::AbsoluteX => MemLocation::from_little_endian(a, b),
-            AddrMode::AbsoluteY => MemLocation::from_little_endian(a, b),
+            AddrMode::AbsoluteX => self.index_location(little_endian_to_big_endian(a, b), self.x),
+            AddrMode::AbsoluteY => self.index_location(little_endian_to_big_endian(a, b), self.y),
+            AddrMode::IndirectX => {
+                // the pointer is read from the zero page, wrapping around within it
+                let pointer = a.wrapping_add(self.x);
+                let low = self.memory_read(MemLocation::page_0(pointer));
+                let high = self.memory_read(MemLocation::page_0(pointer.wrapping_add(1)));
+                MemLocation::from_little_endian(low, high)
+            }
+            AddrMode::IndirectY => {
+                let low = self.memory_read(MemLocation::page_0(a));
+                let high = self.memory_read(MemLocation::page_0(a.wrapping_add(1)));
+                self.index_location(little_endian_to_big_endian(low, high), self.y)
+            }
             _ => panic!("Invalid address mode {:?} in get_location", addr_mode),
         }
     }
 
+    /// Add an index register to a base address, noting if this crosses a page boundary
+    fn index_location(&mut self, base: u16, index: u8) -> MemLocation {
+        let address = base.wrapping_add(index as u16);
+        self.page_crossed = base & 0xFF00 != address & 0xFF00;
+        MemLocation(address)
+    }
+
     fn jump(&mut self, addr_mode: &AddrMode) {
         let a = self.pc_next();
         let b = self.pc_next();
@@ -254,9 +332,13 @@ impl Cpu {
 
         self.pc = match addr_mode {
             AddrMode::Absolute => jump_address,
-            AddrMode::Implicit => {
+            AddrMode::Indirect => {
                 let first = self.bus.read(MemLocation(jump_address));
-                let second = self.bus.read(MemLocation(jump_address + 1));
+                // the high byte is read without carrying into the page, so jmp ($10FF) reads
+                // it from $1000
+                let second = self.bus.read(MemLocation(
+                    (jump_address & 0xFF00) | (jump_address.wrapping_add(1) & 0x00FF),
+                ));
                 little_endian_to_big_endian(first, second)
             }
             _ => panic!("Invalid addressing mode: {:?} in jump", addr_mode),
@@ -286,13 +368,20 @@ impl Cpu {
         self.pc = return_addr;
     }
 
-    fn interrupt(&mut self, address: MemLocation) {
-        let return_addr = (self.pc - 1).to_le_bytes();
-        self.stack_push(return_addr[0]);
+    /// Push the program counter and status, then jump to the handler at the vector `address`
+    fn interrupt(&mut self, address: MemLocation, brk: bool) {
+        let return_addr = self.pc.to_le_bytes();
         self.stack_push(return_addr[1]);
+        self.stack_push(return_addr[0]);
 
-        let status = self.status.byte;
-        self.stack_push(status);
+        // the break flag only exists in the pushed copy of the status, and is only set by brk
+        let mut status = CpuStatus {
+            byte: self.status.byte,
+        };
+        status.set_break(brk);
+        self.stack_push(status.byte);
+
+        self.status.set_int_disable(true);
 
         self.pc = little_endian_to_big_endian(
             self.memory_read(address),
@@ -300,9 +389,24 @@ impl Cpu {
         )
     }
 
+    /// Run the 7 cycle interrupt sequence for an nmi or irq, returning the number of cycles it took
+    fn hardware_interrupt(&mut self, address: MemLocation) -> usize {
+        self.interrupt(address, false);
+        self.irq_masked = true;
+
+        self.cycles += 7;
+        7
+    }
+
+    /// Set the status from a byte pulled off the stack, where the break flag and bit 5 don't exist
+    fn pull_status(&mut self, status: u8) {
+        self.status.byte = status | 1 << 5;
+        self.status.set_break(false);
+    }
+
     fn ret_interrupt(&mut self) {
         let status = self.stack_pop();
-        self.status.byte = status;
+        self.pull_status(status);
 
         let a = self.stack_pop();
         let b = self.stack_pop();
@@ -320,6 +424,14 @@ impl Cpu {
         self.bus.write(MemLocation(location.0), value);
     }
 
+    /// Read the value for a read-modify-write instruction, which write the unmodified value back
+    /// before writing the result on the next cycle
+    fn memory_read_modify(&mut self, location: MemLocation) -> u8 {
+        let value = self.memory_read(location);
+        self.memory_write(value, location);
+        value
+    }
+
     fn pc_next(&mut self) -> u8 {
         let value = self.bus.read(MemLocation(self.pc));
         self.pc += 1;
@@ -374,13 +486,12 @@ impl Cpu {
         // need to grab value to advance program counter
         let value = self.get_value(&AddrMode::Relative);
         if should_branch {
-            let value_i8: i8 = unsafe { std::mem::transmute(value) };
+            let target = self.pc.wrapping_add(value as i8 as u16);
 
-            if value_i8 >= 0 {
-                self.pc += value_i8 as u16;
-            } else {
-                self.pc -= value_i8.unsigned_abs() as u16;
-            }
+            self.branch_taken = true;
+            self.page_crossed = self.pc & 0xFF00 != target & 0xFF00;
+
+            self.pc = target;
         }
     }
 
@@ -398,10 +509,10 @@ impl Cpu {
     }
 
     fn dec_memory(&mut self, location: MemLocation) {
-        let value = self.memory_read(location);
-        self.memory_write(value - 1, location);
+        let value = self.memory_read_modify(location);
+        let new_value = value.wrapping_sub(1);
+        self.memory_write(new_value, location);
 
-        let new_value = self.memory_read(location);
         self.status.set_zero(new_value == 0);
         self.status.set_negative(new_value << 7 == 1);
     }
@@ -492,7 +603,7 @@ impl Cpu {
     }
 
     fn shift_left_memory(&mut self, location: MemLocation) {
-        let mut value = self.memory_read(location);
+        let mut value = self.memory_read_modify(location);
         self.bitshift_and_set_flags(&mut value, ShiftDirection::Left);
 
         self.memory_write(value, location);
@@ -506,7 +617,7 @@ impl Cpu {
     }
 
     fn shift_right_memory(&mut self, location: MemLocation) {
-        let mut value = self.memory_read(location);
+        let mut value = self.memory_read_modify(location);
         self.bitshift_and_set_flags(&mut value, ShiftDirection::Right);
 
         self.memory_write(value, location);
@@ -544,7 +655,7 @@ impl Cpu {
     }
 
     fn rotate_left_memory(&mut self, location: MemLocation) {
-        let mut value = self.memory_read(location);
+        let mut value = self.memory_read_modify(location);
         self.rotate_and_set_flags(&mut value, ShiftDirection::Left);
 
         self.memory_write(value, location);
@@ -558,7 +669,7 @@ impl Cpu {
     }
 
     fn rotate_right_memory(&mut self, location: MemLocation) {
-        let mut value = self.memory_read(location);
+        let mut value = self.memory_read_modify(location);
         self.rotate_and_set_flags(&mut value, ShiftDirection::Right);
 
         self.memory_write(value, location);
@@ -579,10 +690,10 @@ impl Cpu {
     }
 
     fn inc_memory(&mut self, location: MemLocation) {
-        let value = self.memory_read(location);
-        self.memory_write(value + 1, location);
+        let value = self.memory_read_modify(location);
+        let new_value = value.wrapping_add(1);
+        self.memory_write(new_value, location);
 
-        let new_value = self.memory_read(location);
         self.status.set_zero(new_value == 0);
         self.status.set_negative(new_value << 7 == 1);
     }
@@ -628,6 +739,12 @@ pub struct CpuStatus {
     byte: u8,
 }
 
+impl Default for CpuStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CpuStatus {
     pub fn new() -> Self {
         Self {
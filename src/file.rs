@@ -1,68 +1,677 @@
-use crate::console::Game;
-use std::fs;
+use crate::{
+    console::{Game, Region},
+    mapper,
+    ppu::Mirroring,
+};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+};
 
-pub fn read_ines_file(path: &str) -> Result<Game, String> {
-    let file = fs::read(path);
-    if let Err(e) = file {
-        return Err(e.to_string());
+/// Why a ROM couldn't be loaded
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The file doesn't start with "NES" and an EOF character
+    BadMagic,
+    /// The file is shorter than its header says it should be
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// PRG ROM is empty, not a whole number of 16 KiB banks, or a size the mapper can't map
+    BadProgramRomSize(usize),
+    UnsupportedMapper(u16),
+    UnsupportedConsoleType(ConsoleType),
+    UnsupportedTiming(Timing),
+    UnsupportedFeature(&'static str),
+    /// A zip or gzip file couldn't be decompressed
+    BadArchive(String),
+    /// A zip file has no .nes file, or none with the requested name
+    NoRomInArchive,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::BadMagic => write!(f, "Invalid NES file header"),
+            Self::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "File is truncated: expected {} bytes, found {}",
+                    expected, actual
+                )
+            }
+            Self::BadProgramRomSize(size) => write!(f, "Invalid PRG ROM size {}", size),
+            Self::UnsupportedMapper(number) => write!(f, "Unsupported mapper {}", number),
+            Self::UnsupportedConsoleType(console_type) => {
+                write!(f, "Unsupported console type {:?}", console_type)
+            }
+            Self::UnsupportedTiming(timing) => write!(f, "Unsupported timing {:?}", timing),
+            Self::UnsupportedFeature(feature) => write!(f, "Unsupported feature: {}", feature),
+            Self::BadArchive(e) => write!(f, "Invalid archive: {}", e),
+            Self::NoRomInArchive => write!(f, "No .nes file found in archive"),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The decoded 16 byte header of an iNES or NES 2.0 file
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomHeader {
+    /// The header is in the NES 2.0 format rather than iNES 1.0
+    pub nes2: bool,
+    pub mapper: u16,
+    /// Variant of the mapper, always 0 for iNES 1.0
+    pub submapper: u8,
+    pub program_rom_size: usize,
+    pub character_rom_size: usize,
+    pub program_ram_size: usize,
+    /// Battery backed PRG ram
+    pub program_nvram_size: usize,
+    pub character_ram_size: usize,
+    /// Battery backed CHR ram
+    pub character_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    /// 512 bytes of trainer data come before PRG ROM
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+}
+
+/// Which CPU and PPU timing the game was made for
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    /// Works on both NTSC and PAL consoles
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the other console types listed in byte 13 of a NES 2.0 header
+    Extended(u8),
+}
+
+const HEADER_LEN: usize = 16;
+const TRAINER_LEN: usize = 512;
+
+impl RomHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, RomError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(RomError::Truncated {
+                expected: HEADER_LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        if bytes[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+            return Err(RomError::BadMagic);
+        }
+
+        let flag_6 = bytes[6];
+        let flag_7 = bytes[7];
+
+        // bit 0 is nametable mirroring, bit 1 is battery backed PRG ram, bit 2 is a trainer, bit
+        // 3 is four-screen vram and the upper nibble is the low nibble of the mapper number
+        let mirroring = if flag_6 & 1 << 3 != 0 {
+            Mirroring::FourScreen
+        } else if flag_6 & 1 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flag_6 & 1 << 1 != 0;
+        let trainer = flag_6 & 1 << 2 != 0;
+
+        // bits 0-1 are the console type, bits 2-3 identify NES 2.0 and the upper nibble is the
+        // next nibble of the mapper number
+        let nes2 = flag_7 & 0b0000_1100 == 0b0000_1000;
+
+        if nes2 {
+            Ok(Self::parse_nes2(bytes, mirroring, battery, trainer))
+        } else {
+            Ok(Self::parse_ines(bytes, mirroring, battery, trainer))
+        }
+    }
+
+    fn parse_ines(bytes: &[u8], mirroring: Mirroring, battery: bool, trainer: bool) -> Self {
+        // old dumping tools wrote their name into bytes 7-15, in which case only flag 6 can
+        // be trusted
+        let archaic = bytes[12..16].iter().any(|&b| b != 0);
+        let (flag_7, flag_8, flag_9) = if archaic {
+            (0, 0, 0)
+        } else {
+            (bytes[7], bytes[8], bytes[9])
+        };
+
+        let character_rom_size = bytes[5] as usize * 0x2000;
+
+        // PRG ram size in 8 KiB units, where 0 also means 8 KiB
+        let program_ram_size = (flag_8 as usize).max(1) * 0x2000;
+        let (program_ram_size, program_nvram_size) = if battery {
+            (0, program_ram_size)
+        } else {
+            (program_ram_size, 0)
+        };
+
+        Self {
+            nes2: false,
+            mapper: (flag_7 & 0xF0) as u16 | (bytes[6] >> 4) as u16,
+            submapper: 0,
+            program_rom_size: bytes[4] as usize * 0x4000,
+            character_rom_size,
+            program_ram_size,
+            program_nvram_size,
+            // carts without CHR ROM have 8 KiB of CHR ram instead
+            character_ram_size: if character_rom_size == 0 { 0x2000 } else { 0 },
+            character_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            timing: if flag_9 & 1 != 0 {
+                Timing::Pal
+            } else {
+                Timing::Ntsc
+            },
+            console_type: match flag_7 & 0b11 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem,
+                _ => ConsoleType::Playchoice10,
+            },
+        }
+    }
+
+    fn parse_nes2(bytes: &[u8], mirroring: Mirroring, battery: bool, trainer: bool) -> Self {
+        // byte 8: mapper number bits 8-11, submapper
+        let mapper =
+            (bytes[7] & 0xF0) as u16 | (bytes[6] >> 4) as u16 | (bytes[8] as u16 & 0x0F) << 8;
+
+        // byte 9 holds the upper bits of both ROM sizes, byte 10 the PRG ram sizes and byte 11
+        // the CHR ram sizes
+        Self {
+            nes2: true,
+            mapper,
+            submapper: bytes[8] >> 4,
+            program_rom_size: nes2_rom_size(bytes[4], bytes[9] & 0x0F, 0x4000),
+            character_rom_size: nes2_rom_size(bytes[5], bytes[9] >> 4, 0x2000),
+            program_ram_size: nes2_ram_size(bytes[10] & 0x0F),
+            program_nvram_size: nes2_ram_size(bytes[10] >> 4),
+            character_ram_size: nes2_ram_size(bytes[11] & 0x0F),
+            character_nvram_size: nes2_ram_size(bytes[11] >> 4),
+            mirroring,
+            battery,
+            trainer,
+            timing: match bytes[12] & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            },
+            console_type: match bytes[7] & 0b11 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem,
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::Extended(bytes[13] & 0x0F),
+            },
+        }
+    }
+}
+
+/// Size in bytes of a NES 2.0 ROM, from its size byte and the nibble holding its upper bits
+fn nes2_rom_size(low: u8, high: u8, unit: usize) -> usize {
+    if high == 0x0F {
+        // exponent-multiplier form: 2^E * (M * 2 + 1) bytes
+        let exponent = (low >> 2) as u32;
+        let multiplier = (low & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((high as usize) << 8 | low as usize) * unit
+    }
+}
+
+/// Size in bytes of NES 2.0 ram, from its shift count
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Decompress `file` if it's a zip or gzip file, otherwise pass it through unchanged
+fn extract<'a>(file: &'a [u8], entry: Option<&str>) -> Result<Cow<'a, [u8]>, RomError> {
+    if file.starts_with(&ZIP_MAGIC) {
+        extract_zip(file, entry).map(Cow::Owned)
+    } else if file.starts_with(&GZIP_MAGIC) {
+        extract_gzip(file).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(file))
+    }
+}
+
+#[cfg(feature = "compressed")]
+fn extract_zip(file: &[u8], entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(file))
+        .map_err(|e| RomError::BadArchive(e.to_string()))?;
+
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| RomError::BadArchive(e.to_string()))?;
+
+        let found = match entry {
+            Some(entry) => file.name() == entry,
+            None => file.name().to_ascii_lowercase().ends_with(".nes"),
+        };
+
+        if found {
+            let mut rom = Vec::new();
+            file.read_to_end(&mut rom)?;
+            return Ok(rom);
+        }
+    }
+
+    Err(RomError::NoRomInArchive)
+}
+
+#[cfg(not(feature = "compressed"))]
+fn extract_zip(_file: &[u8], _entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    Err(RomError::UnsupportedFeature(
+        "zip files, build with the compressed feature",
+    ))
+}
+
+#[cfg(feature = "compressed")]
+fn extract_gzip(file: &[u8]) -> Result<Vec<u8>, RomError> {
+    let mut rom = Vec::new();
+    flate2::read::GzDecoder::new(file)
+        .read_to_end(&mut rom)
+        .map_err(|e| RomError::BadArchive(e.to_string()))?;
+    Ok(rom)
+}
+
+#[cfg(not(feature = "compressed"))]
+fn extract_gzip(_file: &[u8]) -> Result<Vec<u8>, RomError> {
+    Err(RomError::UnsupportedFeature(
+        "gzip files, build with the compressed feature",
+    ))
+}
+
+impl Game {
+    /// Load a game from the contents of an iNES or NES 2.0 file, or a zip or gzip file holding
+    /// one. The first .nes file in a zip is used
+    pub fn from_bytes(file: &[u8]) -> Result<Self, RomError> {
+        Self::from_ines(&extract(file, None)?)
+    }
+
+    /// Load a game from the .nes file called `entry` in a zip file
+    pub fn from_archive(file: &[u8], entry: &str) -> Result<Self, RomError> {
+        Self::from_ines(&extract(file, Some(entry))?)
+    }
+
+    fn from_ines(file: &[u8]) -> Result<Self, RomError> {
+        let header = RomHeader::parse(file)?;
+
+        if header.console_type != ConsoleType::Nes {
+            return Err(RomError::UnsupportedConsoleType(header.console_type));
+        }
+
+        let region = match header.timing {
+            Timing::Pal => Region::Pal,
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            timing => return Err(RomError::UnsupportedTiming(timing)),
+        };
+
+        // mappers switch PRG ROM in 8 and 16 KiB banks, so anything else can't be mapped
+        if header.program_rom_size == 0 || header.program_rom_size % 0x4000 != 0 {
+            return Err(RomError::BadProgramRomSize(header.program_rom_size));
+        }
+
+        // the trainer, if there is one, comes between the header and PRG ROM
+        let start_prg_byte = if header.trainer {
+            HEADER_LEN + TRAINER_LEN
+        } else {
+            HEADER_LEN
+        };
+        let end_prg_byte = start_prg_byte.saturating_add(header.program_rom_size);
+        let end_chr_byte = end_prg_byte.saturating_add(header.character_rom_size);
+        if file.len() < end_chr_byte {
+            return Err(RomError::Truncated {
+                expected: end_chr_byte,
+                actual: file.len(),
+            });
+        }
+
+        let trainer = header
+            .trainer
+            .then(|| file[HEADER_LEN..start_prg_byte].to_vec());
+
+        // copy program rom
+        let program_rom = file[start_prg_byte..end_prg_byte].to_vec();
+
+        // copy char rom. Carts without any use chr ram instead
+        let character_rom = file[end_prg_byte..end_chr_byte].to_vec();
+
+        // every mapper gets at least 8 KiB of PRG ram
+        let program_ram_size = (header.program_ram_size + header.program_nvram_size).max(0x2000);
+
+        let cartridge = mapper::create(
+            header.mapper,
+            program_rom,
+            character_rom,
+            program_ram_size,
+            header.mirroring,
+        )?;
+
+        Ok(Self {
+            cartridge,
+            save_path: None,
+            trainer,
+            region,
+            header,
+        })
     }
-    let file = file.unwrap();
 
-    // decode header (first 16 bytes)
-    if file[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
-        return Err("Invalid NES file header".to_string());
+    /// Load a game by reading an iNES or NES 2.0 file to the end
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, RomError> {
+        let mut file = Vec::new();
+        reader.read_to_end(&mut file)?;
+        Self::from_bytes(&file)
     }
+}
+
+/// Load a game from an iNES or NES 2.0 file, or a zip or gzip file holding one, with its
+/// battery backed ram saved next to it. `entry` picks a file in a zip instead of the first
+/// .nes file
+pub fn read_ines_file(path: &str, entry: Option<&str>) -> Result<Game, RomError> {
+    let file = fs::read(path)?;
+    let mut game = Game::from_ines(&extract(&file, entry)?)?;
+
+    // battery backed ram is kept in a .sav file next to the rom
+    if game.header.battery {
+        game.save_path = Some(Path::new(path).with_extension("sav"));
+        game.load_save()?;
+    }
+
+    Ok(game)
+}
 
-    let prg_size = file[4];
-    let chr_size = file[5];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::MemLocation;
 
-    let flag_6 = file[6];
-    let flag_7 = file[7];
-    let flag_8 = file[8];
-    let flag_9 = file[9];
-    let flag_10 = file[10];
-    // bytes 11-16 are unused
+    /// An iNES 1.0 header for `prg_banks` 16 KiB PRG banks, `chr_banks` 8 KiB CHR banks and
+    /// `mapper`, followed by that much zeroed data
+    fn ines(prg_banks: u8, chr_banks: u8, mapper: u8) -> Vec<u8> {
+        let mut file = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks];
+        file.extend([mapper << 4, mapper & 0xF0]);
+        file.resize(HEADER_LEN, 0);
+        file.resize(
+            HEADER_LEN + prg_banks as usize * 0x4000 + chr_banks as usize * 0x2000,
+            0,
+        );
+        file
+    }
 
-    if flag_6 != 1 {
-        return Err("Unsupported flag 6".to_string());
+    #[test]
+    fn short_header() {
+        let result = RomHeader::parse(&[0x4E, 0x45, 0x53]);
+        assert!(matches!(
+            result,
+            Err(RomError::Truncated {
+                expected: 16,
+                actual: 3
+            })
+        ));
     }
 
-    if flag_7 != 0 {
-        return Err("Unsupported flag 7".to_string());
+    #[test]
+    fn bad_magic() {
+        let mut file = ines(1, 1, 0);
+        file[3] = 0;
+        assert!(matches!(Game::from_bytes(&file), Err(RomError::BadMagic)));
     }
 
-    if flag_8 != 0 {
-        return Err("Unsupported feature: PRG ram".to_string());
+    #[test]
+    fn truncated_program_rom() {
+        let file = ines(2, 1, 0);
+        let result = Game::from_bytes(&file[..0x5000]);
+        assert!(matches!(
+            result,
+            Err(RomError::Truncated {
+                expected: 0xA010,
+                actual: 0x5000
+            })
+        ));
     }
 
-    if flag_9 != 0 {
-        return Err("Unsupported feature: PAL video".to_string());
+    #[test]
+    fn truncated_character_rom() {
+        let file = ines(1, 1, 0);
+        let result = Game::from_bytes(&file[..file.len() - 1]);
+        assert!(matches!(
+            result,
+            Err(RomError::Truncated {
+                expected: 0x6010,
+                actual: 0x600F
+            })
+        ));
     }
 
-    if flag_10 != 0 {
-        return Err("Unsupported flag 10".to_string());
+    #[test]
+    fn empty_program_rom() {
+        let result = Game::from_bytes(&ines(0, 1, 0));
+        assert!(matches!(result, Err(RomError::BadProgramRomSize(0))));
     }
 
-    if prg_size > 2 {
-        return Err("Unsupported feature: rom mapping".to_string());
+    #[test]
+    fn nrom_48k_program_rom() {
+        let result = Game::from_bytes(&ines(3, 1, 0));
+        assert!(matches!(result, Err(RomError::BadProgramRomSize(0xC000))));
     }
 
-    if chr_size != 1 {
-        return Err("Unsupported feature: non-standard chr-rom".to_string());
+    #[test]
+    fn unknown_mapper() {
+        let result = Game::from_bytes(&ines(2, 1, 0x55));
+        assert!(matches!(result, Err(RomError::UnsupportedMapper(0x55))));
     }
 
-    // copy program rom
-    let mut program_rom = [0; 0x8000];
-    let end_prg_byte = 0x10 + 0x4000 * prg_size as usize;
-    program_rom.clone_from_slice(&file[0x10..end_prg_byte]);
+    #[test]
+    fn valid_rom() {
+        let game = Game::from_bytes(&ines(2, 1, 1)).unwrap();
+        assert_eq!(game.header.mapper, 1);
+        assert_eq!(game.header.program_rom_size, 0x8000);
+        assert_eq!(game.header.character_rom_size, 0x2000);
+        assert_eq!(game.region, Region::Ntsc);
+    }
 
-    // copy char rom
-    let mut character_rom = [0; 0x2000];
-    let end_chr_byte = end_prg_byte + 0x2000 * chr_size as usize;
-    character_rom.clone_from_slice(&file[end_prg_byte..end_chr_byte]);
+    #[test]
+    fn nes2_exponent_multiplier_sizes() {
+        let mut file = ines(0, 0, 0);
+        // NES 2.0, mapper 0x104 submapper 2
+        file[6] = 0x40;
+        file[7] = 0x08;
+        file[8] = 0x21;
+        // PRG: 2^10 * 3, CHR: 2^13 * 1
+        file[4] = 10 << 2 | 1;
+        file[5] = 13 << 2;
+        file[9] = 0xFF;
+        // 8 KiB PRG ram, 32 KiB PRG nvram, 8 KiB CHR ram
+        file[10] = 0x97;
+        file[11] = 0x07;
 
-    Ok(Game {
-        program_rom,
-        character_rom,
-    })
+        let header = RomHeader::parse(&file).unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper, 0x104);
+        assert_eq!(header.submapper, 2);
+        assert_eq!(header.program_rom_size, 3 * 1024);
+        assert_eq!(header.character_rom_size, 8 * 1024);
+        assert_eq!(header.program_ram_size, 0x2000);
+        assert_eq!(header.program_nvram_size, 0x8000);
+        assert_eq!(header.character_ram_size, 0x2000);
+        assert_eq!(header.character_nvram_size, 0);
+    }
+
+    #[test]
+    fn nes2_large_program_rom() {
+        let mut file = ines(0, 0, 0);
+        file[7] = 0x08;
+        // the upper nibble extends the size in 16 KiB units
+        file[4] = 0x02;
+        file[9] = 0x01;
+
+        let header = RomHeader::parse(&file).unwrap();
+        assert_eq!(header.program_rom_size, 0x102 * 0x4000);
+    }
+
+    #[test]
+    fn archaic_header() {
+        let mut file = ines(2, 1, 0x01);
+        file[7..16].copy_from_slice(b"DiskDude!");
+
+        let header = RomHeader::parse(&file).unwrap();
+        assert!(!header.nes2);
+        // the "D" in byte 7 would otherwise make this mapper 0x41
+        assert_eq!(header.mapper, 0x01);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(header.timing, Timing::Ntsc);
+        assert!(Game::from_bytes(&file).is_ok());
+    }
+
+    #[test]
+    fn ines_battery_ram() {
+        let mut file = ines(1, 0, 0);
+        file[6] |= 1 << 1;
+        file[8] = 2;
+
+        let header = RomHeader::parse(&file).unwrap();
+        assert!(header.battery);
+        assert_eq!(header.program_ram_size, 0);
+        assert_eq!(header.program_nvram_size, 0x4000);
+        assert_eq!(header.character_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn trainer() {
+        let mut file = ines(1, 1, 0);
+        file[6] |= 1 << 2;
+        let trainer: Vec<u8> = (0..TRAINER_LEN).map(|i| i as u8 ^ 0x5A).collect();
+        file.splice(HEADER_LEN..HEADER_LEN, trainer.iter().copied());
+        file[HEADER_LEN + TRAINER_LEN] = 0xA5;
+
+        let game = Game::from_bytes(&file).unwrap();
+        assert_eq!(game.trainer.as_deref(), Some(&trainer[..]));
+        assert_eq!(game.cartridge.borrow_mut().cpu_read(0x8000), 0xA5);
+
+        let mut console = crate::console::Console::new(game);
+        assert_eq!(console.cpu.bus.read(MemLocation(0x7000)), trainer[0]);
+        assert_eq!(console.cpu.bus.read(MemLocation(0x71FF)), trainer[0x1FF]);
+    }
+
+    #[test]
+    fn nes2_timing_and_console_type() {
+        let mut file = ines(1, 1, 0);
+        file[7] = 0x08 | 0b11;
+        file[12] = 3;
+        file[13] = 0x05;
+
+        let header = RomHeader::parse(&file).unwrap();
+        assert_eq!(header.timing, Timing::Dendy);
+        assert_eq!(header.console_type, ConsoleType::Extended(5));
+        assert!(matches!(
+            Game::from_bytes(&file),
+            Err(RomError::UnsupportedConsoleType(ConsoleType::Extended(5)))
+        ));
+
+        file[7] = 0x08;
+        file[12] = 1;
+        let game = Game::from_bytes(&file).unwrap();
+        assert_eq!(game.region, Region::Pal);
+    }
+
+    #[cfg(feature = "compressed")]
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "compressed")]
+    #[test]
+    fn gzip_archive() {
+        use std::io::Write;
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&ines(1, 1, 2)).unwrap();
+        let file = gzip.finish().unwrap();
+
+        let game = Game::from_archive(&file, "ignored.nes").unwrap();
+        assert_eq!(game.header.mapper, 2);
+        assert_eq!(Game::from_bytes(&file).unwrap().header.mapper, 2);
+    }
+
+    #[cfg(feature = "compressed")]
+    #[test]
+    fn zip_archive() {
+        let file = zip(&[
+            ("readme.txt", b"not a rom"),
+            ("first.NES", &ines(1, 1, 2)),
+            ("second.nes", &ines(1, 1, 3)),
+        ]);
+
+        // the first .nes file, whatever its case, unless another is named
+        assert_eq!(Game::from_bytes(&file).unwrap().header.mapper, 2);
+        let game = Game::from_archive(&file, "second.nes").unwrap();
+        assert_eq!(game.header.mapper, 3);
+    }
+
+    #[cfg(feature = "compressed")]
+    #[test]
+    fn no_rom_in_archive() {
+        let file = zip(&[("readme.txt", b"not a rom")]);
+
+        assert!(matches!(
+            Game::from_bytes(&file),
+            Err(RomError::NoRomInArchive)
+        ));
+        assert!(matches!(
+            Game::from_archive(&zip(&[("game.nes", &ines(1, 1, 0))]), "other.nes"),
+            Err(RomError::NoRomInArchive)
+        ));
+    }
 }
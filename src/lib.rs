@@ -0,0 +1,10 @@
+pub mod apu;
+pub mod audio;
+pub mod bus;
+pub mod console;
+pub mod controller;
+pub mod cpu;
+pub mod file;
+pub mod mapper;
+mod opcode;
+pub mod ppu;
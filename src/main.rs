@@ -1,16 +1,41 @@
-mod bus;
-mod console;
-mod cpu;
-mod file;
-mod opcode;
-mod ppu;
+use std::{io, sync::mpsc, thread};
+
+use nesquik::{console::Console, file};
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let game = crate::file::read_ines_file(&args[1]).unwrap();
+    // --trace prints every instruction as it runs
+    let (flags, args): (Vec<String>, Vec<String>) =
+        std::env::args().partition(|arg| arg == "--trace");
+    let trace = !flags.is_empty();
+
+    // an optional second argument names the rom to load from a zip file
+    let entry = args.get(2).map(String::as_str);
+    let game = file::read_ines_file(&args[1], entry).unwrap();
+
+    let mut nes = Console::new(game);
+    nes.cpu.trace = trace;
+    if trace {
+        println!("{}", nes.cpu);
+    }
+
+    // run until enter is pressed
+    let (quit_sender, quit) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        if let Ok(1..) = io::stdin().read_line(&mut line) {
+            let _ = quit_sender.send(());
+        }
+    });
+
+    while quit.try_recv().is_err() {
+        nes.run_frame();
 
-    let mut nes = crate::console::Console::new(game);
-    println!("{}", nes.cpu);
+        if let Some(e) = nes.take_save_error() {
+            eprintln!("Failed to write save file: {}", e);
+        }
+    }
 
-    nes.run_continuous();
+    if let Err(e) = nes.save() {
+        eprintln!("Failed to write save file: {}", e);
+    }
 }
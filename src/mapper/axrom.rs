@@ -0,0 +1,70 @@
+use super::{CharacterMemory, Mapper, ProgramRam};
+use crate::ppu::Mirroring;
+
+/// Mapper 7, with a switchable 32 KiB PRG bank and switchable single-screen mirroring
+pub struct Axrom {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    program_bank: u8,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn new(program_rom: Vec<u8>, program_ram: ProgramRam, character: CharacterMemory) -> Self {
+        Self {
+            program_rom,
+            program_ram,
+            character,
+            program_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.read(addr),
+            0x8000..=0xFFFF => {
+                let index = self.program_bank as usize * 0x8000 + (addr as usize - 0x8000);
+                self.program_rom[index % self.program_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.write(addr, value),
+            // only some AxROM boards have bus conflicts, and games for the others rely on not
+            // having them, so they aren't emulated
+            0x8000..=0xFFFF => {
+                self.program_bank = value & 0b111;
+                // bit 4 selects which nametable is used for all four
+                self.mirroring = if value & 1 << 4 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
@@ -0,0 +1,65 @@
+use super::{bus_conflict, CharacterMemory, Mapper, ProgramRam};
+use crate::ppu::Mirroring;
+
+/// Mapper 3, with fixed PRG ROM like NROM and a switchable 8 KiB CHR bank
+pub struct Cnrom {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    mirroring: Mirroring,
+    character_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(
+        program_rom: Vec<u8>,
+        program_ram: ProgramRam,
+        character: CharacterMemory,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+            character_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.read(addr),
+            // 16 KiB roms are mirrored into both halves
+            0x8000..=0xFFFF => self.program_rom[(addr as usize - 0x8000) % self.program_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.write(addr, value),
+            0x8000..=0xFFFF => self.character_bank = bus_conflict(self.cpu_read(addr), value),
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character
+            .read(self.character_bank as usize * 0x2000 + addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character
+            .write(self.character_bank as usize * 0x2000 + addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
@@ -0,0 +1,154 @@
+use super::{CharacterMemory, Mapper, ProgramRam};
+use crate::ppu::Mirroring;
+
+/// Mapper 1, with switchable 16/32 KiB PRG banks, 4/8 KiB CHR banks, mirroring control and
+/// 8 KiB of PRG RAM. Registers are written one bit at a time through a serial shift register
+pub struct Mmc1 {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    // bits are shifted in from the left, the 1 reaching bit 0 marks the 5th write
+    shift: u8,
+    // a write happened during this instruction. Read-modify-write instructions are the only ones
+    // to write twice, on consecutive cycles, and the second write is ignored
+    written: bool,
+    control: u8,
+    character_bank_0: u8,
+    character_bank_1: u8,
+    program_bank: u8,
+}
+
+const SHIFT_RESET: u8 = 0b1_0000;
+
+impl Mmc1 {
+    pub fn new(program_rom: Vec<u8>, program_ram: ProgramRam, character: CharacterMemory) -> Self {
+        Self {
+            program_rom,
+            program_ram,
+            character,
+            shift: SHIFT_RESET,
+            written: false,
+            // starts with the last bank fixed at $C000
+            control: 0b0_1100,
+            character_bank_0: 0,
+            character_bank_1: 0,
+            program_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        // registers are selected by bits 13 and 14 of the address of the 5th write
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.character_bank_0 = value,
+            0xC000..=0xDFFF => self.character_bank_1 = value,
+            0xE000..=0xFFFF => self.program_bank = value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn program_ram_enabled(&self) -> bool {
+        self.program_bank & 1 << 4 == 0
+    }
+
+    /// Index into PRG ROM of a cpu address in $8000-$FFFF
+    fn program_index(&self, addr: u16) -> usize {
+        let bank = (self.program_bank & 0x0F) as usize;
+        let last_bank = self.program_rom.len() / 0x4000 - 1;
+        let offset = addr as usize & 0x3FFF;
+
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            // switch 32 KiB at $8000, ignoring the low bit of the bank number
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            // fix the first bank at $8000 and switch 16 KiB at $C000
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            // fix the last bank at $C000 and switch 16 KiB at $8000
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last_bank,
+        };
+
+        (bank * 0x4000 + offset) % self.program_rom.len()
+    }
+
+    /// Index into CHR memory of a ppu address in $0000-$1FFF
+    fn character_index(&self, addr: u16) -> usize {
+        let offset = addr as usize & 0x0FFF;
+
+        let bank = if self.control & 1 << 4 == 0 {
+            // switch 8 KiB at once, ignoring the low bit of the bank number
+            (self.character_bank_0 & !1) as usize + (addr >= 0x1000) as usize
+        } else if addr < 0x1000 {
+            // switch two separate 4 KiB banks
+            self.character_bank_0 as usize
+        } else {
+            self.character_bank_1 as usize
+        };
+
+        bank * 0x1000 + offset
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.program_ram_enabled() => self.program_ram.read(addr),
+            0x8000..=0xFFFF => self.program_rom[self.program_index(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.program_ram_enabled() => self.program_ram.write(addr, value),
+            0x8000..=0xFFFF => {
+                if self.written {
+                    return;
+                }
+                self.written = true;
+
+                if value & 1 << 7 != 0 {
+                    // reset the shift register, and fix the last bank at $C000
+                    self.shift = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+
+                let complete = self.shift & 1 != 0;
+                self.shift = self.shift >> 1 | (value & 1) << 4;
+
+                if complete {
+                    self.write_register(addr, self.shift);
+                    self.shift = SHIFT_RESET;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character.read(self.character_index(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character.write(self.character_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.written = false;
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
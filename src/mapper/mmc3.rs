@@ -0,0 +1,181 @@
+use super::{CharacterMemory, Mapper, ProgramRam};
+use crate::ppu::Mirroring;
+
+/// Mapper 4, with 8 KiB PRG banks, 1/2 KiB CHR banks, mirroring control, 8 KiB of PRG RAM and
+/// an irq counter clocked by the ppu's scanline fetches
+pub struct Mmc3 {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    mirroring: Mirroring,
+    // register written by the next bank data write, and the PRG/CHR inversion modes
+    bank_select: u8,
+    banks: [u8; 8],
+    program_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    // dots since ppu A12 went low, so quick toggles between fetches can be ignored
+    a12_low_dots: u8,
+}
+
+/// Dots A12 needs to stay low for its next rising edge to clock the counter, around 3 cpu cycles
+const A12_FILTER_DOTS: u8 = 9;
+
+impl Mmc3 {
+    pub fn new(
+        program_rom: Vec<u8>,
+        program_ram: ProgramRam,
+        character: CharacterMemory,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+            bank_select: 0,
+            banks: [0; 8],
+            program_ram_protect: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            a12_low_dots: 0,
+        }
+    }
+
+    fn program_ram_enabled(&self) -> bool {
+        self.program_ram_protect & 1 << 7 != 0
+    }
+
+    fn program_ram_writable(&self) -> bool {
+        self.program_ram_enabled() && self.program_ram_protect & 1 << 6 == 0
+    }
+
+    /// Index into PRG ROM of a cpu address in $8000-$FFFF
+    fn program_index(&self, addr: u16) -> usize {
+        let second_last_bank = self.program_rom.len() / 0x2000 - 2;
+        let window = (addr as usize - 0x8000) / 0x2000;
+
+        // bit 6 swaps the switchable $8000 bank and the fixed $C000 bank
+        let bank = match (self.bank_select & 1 << 6 != 0, window) {
+            (false, 0) | (true, 2) => self.banks[6] as usize,
+            (_, 1) => self.banks[7] as usize,
+            (false, 2) | (true, 0) => second_last_bank,
+            (_, _) => second_last_bank + 1,
+        };
+
+        (bank * 0x2000 + (addr as usize & 0x1FFF)) % self.program_rom.len()
+    }
+
+    /// Index into CHR memory of a ppu address in $0000-$1FFF
+    fn character_index(&self, addr: u16) -> usize {
+        // bit 7 swaps the 2 KiB banks at $0000 with the 1 KiB banks at $1000
+        let addr = if self.bank_select & 1 << 7 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+
+        let bank = match addr {
+            // 2 KiB banks ignore the low bit of the bank number
+            0x0000..=0x07FF => self.banks[0] as usize & !1 | (addr as usize >> 10 & 1),
+            0x0800..=0x0FFF => self.banks[1] as usize & !1 | (addr as usize >> 10 & 1),
+            _ => self.banks[2 + (addr as usize - 0x1000) / 0x400] as usize,
+        };
+
+        bank * 0x400 + (addr as usize & 0x03FF)
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.program_ram_enabled() => self.program_ram.read(addr),
+            0x8000..=0xFFFF => self.program_rom[self.program_index(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        // each pair of registers is selected by bits 13 and 14, and then by bit 0
+        match (addr, addr & 1 == 0) {
+            (0x6000..=0x7FFF, _) if self.program_ram_writable() => {
+                self.program_ram.write(addr, value)
+            }
+            (0x8000..=0x9FFF, true) => self.bank_select = value,
+            (0x8000..=0x9FFF, false) => self.banks[(self.bank_select & 0b111) as usize] = value,
+            // cartridges with four-screen vram ignore this
+            (0xA000..=0xBFFF, true) if self.mirroring != Mirroring::FourScreen => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (0xA000..=0xBFFF, false) => self.program_ram_protect = value,
+            (0xC000..=0xDFFF, true) => self.irq_latch = value,
+            (0xC000..=0xDFFF, false) => {
+                // the counter is reloaded on its next clock
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, true) => {
+                // disabling also acknowledges a pending irq
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character.read(self.character_index(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character.write(self.character_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn ppu_clock(&mut self, addr: u16) {
+        // with background and sprite patterns in different tables, A12 rises once per scanline
+        if addr & 0x1000 != 0 {
+            if self.a12_low_dots >= A12_FILTER_DOTS {
+                self.clock_irq_counter();
+            }
+            self.a12_low_dots = 0;
+        } else {
+            self.a12_low_dots = self.a12_low_dots.saturating_add(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
@@ -0,0 +1,163 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{file::RomError, ppu::Mirroring};
+
+mod axrom;
+mod cnrom;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod uxrom;
+
+use axrom::Axrom;
+use cnrom::Cnrom;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use uxrom::Uxrom;
+
+/// Cartridge hardware, which decides what the cpu and ppu see in the address space the
+/// cartridge is connected to
+pub trait Mapper {
+    /// Read from cpu address space, $4020-$FFFF
+    fn cpu_read(&mut self, addr: u16) -> u8;
+
+    /// Write to cpu address space, $4020-$FFFF
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    /// Read from ppu address space, $0000-$1FFF
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    /// Write to ppu address space, $0000-$1FFF
+    fn ppu_write(&mut self, addr: u16, value: u8);
+
+    /// How the nametables are currently mirrored
+    fn mirroring(&self) -> Mirroring;
+
+    /// Called once for each cycle an instruction took, all together after it has finished, and
+    /// once for each cycle the cpu is stalled by dma
+    fn cpu_clock(&mut self) {}
+
+    /// Called once every ppu dot with the address last put on the ppu address bus, $0000-$3FFF
+    fn ppu_clock(&mut self, _addr: u16) {}
+
+    /// State of the cartridge's irq output
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Work ram at $6000-$7FFF, if the cartridge has any
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Create the mapper numbered `number` in the iNES format
+pub fn create(
+    number: u16,
+    program_rom: Vec<u8>,
+    character_rom: Vec<u8>,
+    program_ram_size: usize,
+    mirroring: Mirroring,
+) -> Result<Rc<RefCell<dyn Mapper>>, RomError> {
+    let program_ram = ProgramRam::new(program_ram_size);
+    let character = CharacterMemory::new(character_rom);
+
+    let mapper: Rc<RefCell<dyn Mapper>> = match number {
+        0 => Rc::new(RefCell::new(Nrom::new(
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+        )?)),
+        1 => Rc::new(RefCell::new(Mmc1::new(program_rom, program_ram, character))),
+        2 => Rc::new(RefCell::new(Uxrom::new(
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+        ))),
+        3 => Rc::new(RefCell::new(Cnrom::new(
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+        ))),
+        4 => Rc::new(RefCell::new(Mmc3::new(
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+        ))),
+        7 => Rc::new(RefCell::new(Axrom::new(
+            program_rom,
+            program_ram,
+            character,
+        ))),
+        _ => return Err(RomError::UnsupportedMapper(number)),
+    };
+
+    Ok(mapper)
+}
+
+/// The value a register on a board without bus conflict prevention sees when `value` is
+/// written to an address holding `rom_byte`. The rom drives the data bus at the same time, so
+/// only bits that are set in both get through
+fn bus_conflict(rom_byte: u8, value: u8) -> u8 {
+    rom_byte & value
+}
+
+/// Pattern table memory on the cartridge. Carts without CHR ROM have 8 KiB of CHR RAM instead
+struct CharacterMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl CharacterMemory {
+    fn new(character_rom: Vec<u8>) -> Self {
+        if character_rom.is_empty() {
+            Self {
+                data: vec![0; 0x2000],
+                writable: true,
+            }
+        } else {
+            Self {
+                data: character_rom,
+                writable: false,
+            }
+        }
+    }
+
+    fn read(&self, index: usize) -> u8 {
+        self.data[index % self.data.len()]
+    }
+
+    fn write(&mut self, index: usize, value: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[index % len] = value;
+        }
+    }
+}
+
+/// Work ram at $6000-$7FFF on the cartridge, kept by a battery on some carts to hold saves
+struct ProgramRam {
+    data: Vec<u8>,
+}
+
+impl ProgramRam {
+    fn new(size: usize) -> Self {
+        Self {
+            data: vec![0; size],
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        self.data[(addr as usize - 0x6000) % self.data.len()]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let len = self.data.len();
+        self.data[(addr as usize - 0x6000) % len] = value;
+    }
+}
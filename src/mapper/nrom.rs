@@ -0,0 +1,63 @@
+use super::{CharacterMemory, Mapper, ProgramRam};
+use crate::{file::RomError, ppu::Mirroring};
+
+/// Mapper 0, with no bank switching: 16 or 32 KiB of PRG ROM and 8 KiB of CHR
+pub struct Nrom {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(
+        program_rom: Vec<u8>,
+        program_ram: ProgramRam,
+        character: CharacterMemory,
+        mirroring: Mirroring,
+    ) -> Result<Self, RomError> {
+        if program_rom.len() != 0x4000 && program_rom.len() != 0x8000 {
+            return Err(RomError::BadProgramRomSize(program_rom.len()));
+        }
+
+        Ok(Self {
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+        })
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.read(addr),
+            // 16 KiB roms are mirrored into both halves
+            0x8000..=0xFFFF => self.program_rom[(addr as usize - 0x8000) % self.program_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.program_ram.write(addr, value);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
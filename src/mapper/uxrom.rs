@@ -0,0 +1,65 @@
+use super::{bus_conflict, CharacterMemory, Mapper, ProgramRam};
+use crate::ppu::Mirroring;
+
+/// Mapper 2, with a switchable 16 KiB PRG bank at $8000 and the last bank fixed at $C000
+pub struct Uxrom {
+    program_rom: Vec<u8>,
+    program_ram: ProgramRam,
+    character: CharacterMemory,
+    mirroring: Mirroring,
+    program_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(
+        program_rom: Vec<u8>,
+        program_ram: ProgramRam,
+        character: CharacterMemory,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            program_rom,
+            program_ram,
+            character,
+            mirroring,
+            program_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x6000..=0x7FFF => return self.program_ram.read(addr),
+            0x8000..=0xBFFF => self.program_bank as usize,
+            0xC000..=0xFFFF => self.program_rom.len() / 0x4000 - 1,
+            _ => return 0,
+        };
+
+        self.program_rom[(bank * 0x4000 + (addr as usize & 0x3FFF)) % self.program_rom.len()]
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.program_ram.write(addr, value),
+            0x8000..=0xFFFF => self.program_bank = bus_conflict(self.cpu_read(addr), value),
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.character.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.character.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn program_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.program_ram.data)
+    }
+}
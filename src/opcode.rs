@@ -209,8 +209,8 @@ lazy_static! {
 
         o.insert(0xE6, Opcode(Instruction::Inc, AddrMode::ZeroPage, CycleLen::Constant(5)));
         o.insert(0xF6, Opcode(Instruction::Inc, AddrMode::ZeroPageX, CycleLen::Constant(6)));
-        o.insert(0xEE, Opcode(Instruction::Inc, AddrMode::ZeroPage, CycleLen::Constant(6)));
-        o.insert(0xFE, Opcode(Instruction::Inc, AddrMode::ZeroPage, CycleLen::Constant(7)));
+        o.insert(0xEE, Opcode(Instruction::Inc, AddrMode::Absolute, CycleLen::Constant(6)));
+        o.insert(0xFE, Opcode(Instruction::Inc, AddrMode::AbsoluteX, CycleLen::Constant(7)));
 
         o.insert(0xE8, Opcode(Instruction::Inx, AddrMode::Implicit, CycleLen::Constant(2)));
 
@@ -254,7 +254,7 @@ lazy_static! {
         o.insert(0x05, Opcode(Instruction::Ora, AddrMode::ZeroPage, CycleLen::Constant(3)));
         o.insert(0x15, Opcode(Instruction::Ora, AddrMode::ZeroPageX, CycleLen::Constant(4)));
         o.insert(0x0D, Opcode(Instruction::Ora, AddrMode::Absolute, CycleLen::Constant(4)));
-        o.insert(0x1D, Opcode(Instruction::Ora, AddrMode::AbsoluteX, CycleLen::Constant(4)));
+        o.insert(0x1D, Opcode(Instruction::Ora, AddrMode::AbsoluteX, CycleLen::PageCrossed(4)));
         o.insert(0x19, Opcode(Instruction::Ora, AddrMode::AbsoluteY, CycleLen::PageCrossed(4)));
         o.insert(0x01, Opcode(Instruction::Ora, AddrMode::IndirectX, CycleLen::Constant(6)));
         o.insert(0x11, Opcode(Instruction::Ora, AddrMode::IndirectY, CycleLen::PageCrossed(5)));
@@ -263,9 +263,9 @@ lazy_static! {
 
         o.insert(0x08, Opcode(Instruction::Php, AddrMode::Implicit, CycleLen::Constant(3)));
 
-        o.insert(0x68, Opcode(Instruction::Pla, AddrMode::Implicit, CycleLen::Constant(3)));
+        o.insert(0x68, Opcode(Instruction::Pla, AddrMode::Implicit, CycleLen::Constant(4)));
 
-        o.insert(0x28, Opcode(Instruction::Plp, AddrMode::Implicit, CycleLen::Constant(3)));
+        o.insert(0x28, Opcode(Instruction::Plp, AddrMode::Implicit, CycleLen::Constant(4)));
 
         o.insert(0x2A, Opcode(Instruction::Rol, AddrMode::Accumulator, CycleLen::Constant(2)));
         o.insert(0x26, Opcode(Instruction::Rol, AddrMode::ZeroPage, CycleLen::Constant(5)));
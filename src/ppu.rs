@@ -1,12 +1,36 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{console::Region, mapper::Mapper};
+
 pub struct Ppu {
+    region: Region,
+    scanline: u16,
+    dot: u16,
+    frame_count: u64,
+    odd_frame: bool,
+    /// Current vram address: fine y (3 bits), nametable (2 bits), coarse y (5 bits), coarse x (5 bits)
+    v: u16,
+    /// Temporary vram address, copied into `v` during rendering and by the second ppuaddr write
+    t: u16,
+    fine_x: u8,
+    /// Result of the previous ppudata read, returned by the next one
+    read_buffer: u8,
+    /// Write toggle shared by ppuscroll and ppuaddr; false on the first write
     w: bool,
     ctrl: u8,
     mask: u8,
     status: u8,
     oam_addr: u8,
-    vram_addr: u16,
+    // state of the nmi output (vblank flag and ppuctrl bit 7), nmis happen on its rising edge
+    nmi_line: bool,
+    nmi_pending: bool,
+    // set when ppustatus is read just before vblank starts, so the flag is never set this frame
+    suppress_vblank: bool,
     oam: [u8; 256],
     memory: PpuMemory,
+    background: BackgroundPipeline,
+    sprites: SpritePipeline,
+    frame: Frame,
 }
 
 #[derive(Debug)]
@@ -19,108 +43,580 @@ pub enum PpuRegister {
     PpuScroll,
     PpuAddr,
     PpuData,
-    OamDma,
 }
 
 /// [line from top][pixel from left]
-pub struct Frame([[u8; 240]; 256]);
+pub struct Frame(pub [[u8; 256]; 240]);
+
+/// Latches and shift registers used to fetch and draw background tiles
+#[derive(Default)]
+struct BackgroundPipeline {
+    next_tile: u8,
+    next_pattern_low: u8,
+    next_pattern_high: u8,
+    next_palette: u8,
+    pattern_low: u16,
+    pattern_high: u16,
+    // each bit of the palette shifters is filled from the palette of the tile it belongs to
+    palette_low: u16,
+    palette_high: u16,
+}
+
+/// Sprites found by sprite evaluation, to be drawn on the next line
+#[derive(Default)]
+struct SpritePipeline {
+    secondary_oam: [u8; 32],
+    count: usize,
+    // sprite 0 was found by evaluation, so it's in slot 0
+    sprite_zero: bool,
+    units: [Sprite; 8],
+}
+
+#[derive(Default, Copy, Clone)]
+struct Sprite {
+    y: u8,
+    tile: u8,
+    attributes: u8,
+    x: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+}
+
+/// How the four logical nametables are mapped onto nametable ram
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mirroring {
+    /// $2000 = $2400 and $2800 = $2C00, for vertical scrolling
+    Horizontal,
+    /// $2000 = $2800 and $2400 = $2C00, for horizontal scrolling
+    Vertical,
+    /// Extra ram on the cartridge gives each nametable its own memory
+    FourScreen,
+    /// All four nametables use the first 1 KiB of nametable ram
+    SingleScreenLower,
+    /// All four nametables use the second 1 KiB of nametable ram
+    SingleScreenUpper,
+}
 
 impl Ppu {
-    pub fn new(character_rom: [u8; 0x2000]) -> Self {
+    pub fn new(cartridge: Rc<RefCell<dyn Mapper>>, region: Region) -> Self {
         Self {
+            region,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            odd_frame: false,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            read_buffer: 0,
             w: false,
             ctrl: 0,
             mask: 0,
             status: 0,
             oam_addr: 0,
-            vram_addr: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            suppress_vblank: false,
             oam: [0; 256],
-            memory: PpuMemory::new(character_rom),
+            memory: PpuMemory::new(cartridge),
+            background: BackgroundPipeline::default(),
+            sprites: SpritePipeline::default(),
+            frame: Frame([[0; 256]; 240]),
         }
     }
-    pub fn draw_frame(&self) -> Frame {
-        let mut frame = Frame([[0x1d; 240]; 256]);
 
-        // FIXME: Render background
+    /// Advance the PPU by one dot
+    pub fn tick(&mut self) {
+        let pre_render_line = self.pre_render_line();
 
-        // Render sprites
-        // FIXME: Correctly render sprites
-        let mut secondary_oam = [0; 32];
+        if self.scanline < 240 || self.scanline == pre_render_line {
+            self.run_render_dot();
+        }
 
-        for scanline in 0..240 {
-            // Fetch the first 8 sprites that appear on this scanline to the secondary oam
-            let mut sec_oam_index = 0;
-            for i in 0..64 {
-                let y = self.oam[i * 4];
-                if y > scanline - 8 && y <= scanline {
-                    secondary_oam[sec_oam_index] = self.oam[i * 4];
-                    secondary_oam[sec_oam_index + 1] = self.oam[i * 4 + 1];
-                    secondary_oam[sec_oam_index + 2] = self.oam[i * 4 + 2];
-                    secondary_oam[sec_oam_index + 3] = self.oam[i * 4 + 3];
+        if self.scanline == 241 && self.dot == 1 {
+            if !self.suppress_vblank {
+                self.status |= 1 << 7;
+                self.update_nmi();
+            }
+            self.suppress_vblank = false;
+        }
+
+        if self.scanline == pre_render_line && self.dot == 1 {
+            // clear vblank, sprite 0 hit and sprite overflow
+            self.status &= !(1 << 7 | 1 << 6 | 1 << 5);
+            self.update_nmi();
+        }
+
+        // the pre-render line is one dot shorter on odd frames when rendering on NTSC
+        if self.scanline == pre_render_line
+            && self.dot == 339
+            && self.odd_frame
+            && self.rendering_enabled()
+            && self.region == Region::Ntsc
+        {
+            self.dot += 1;
+        }
+
+        self.memory.clock();
+
+        self.dot += 1;
 
-                    sec_oam_index += 1;
+        if self.dot > 340 {
+            self.dot = 0;
+            self.scanline += 1;
+
+            // the frame is complete once the last visible line has been drawn
+            if self.scanline == 240 {
+                self.frame_count += 1;
+            }
 
-                    if sec_oam_index == 8 {
-                        break;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    /// Returns true once for every nmi the PPU has raised
+    pub fn poll_nmi(&mut self) -> bool {
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        nmi
+    }
+
+    fn update_nmi(&mut self) {
+        // vblank flag and nmi enable
+        let line = self.status & 1 << 7 != 0 && self.ctrl & 1 << 7 != 0;
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = line;
+    }
+
+    /// Number of frames completed since power on, counted when line 239 has been drawn
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The most recently drawn frame. Lines are overwritten as they are drawn, so this is only
+    /// a complete picture between the end of line 239 and the start of the next frame
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    fn pre_render_line(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        // show background or show sprites
+        self.mask & 0b0001_1000 != 0
+    }
+
+    /// Run one dot of a visible or pre-render scanline
+    fn run_render_dot(&mut self) {
+        if self.rendering_enabled() {
+            if (2..=257).contains(&self.dot) || (321..=337).contains(&self.dot) {
+                self.shift_background();
+
+                // each tile takes 8 dots to fetch: nametable, attribute, pattern low, pattern high
+                match (self.dot - 1) % 8 {
+                    0 => {
+                        self.load_background_shifters();
+                        self.background.next_tile = self.memory.read(self.nametable_address());
+                    }
+                    2 => self.background.next_palette = self.fetch_attribute(),
+                    4 => {
+                        let address = self.pattern_address();
+                        self.background.next_pattern_low = self.memory.read(address);
+                    }
+                    6 => {
+                        let address = self.pattern_address() + 8;
+                        self.background.next_pattern_high = self.memory.read(address);
                     }
+                    7 => self.increment_coarse_x(),
+                    _ => (),
                 }
             }
 
-            // Render sprite pixels
-            for sprite in 0..sec_oam_index {
-                for pixel in 0..8 {
-                    let pattern_x = pixel;
-                    let pattern_y = scanline - secondary_oam[sprite];
+            if self.dot == 256 {
+                self.increment_y();
+            }
+
+            // start fetching the first two tiles of the next line
+            if self.dot == 257 {
+                self.copy_horizontal_position();
+            }
+
+            if self.scanline == self.pre_render_line() && (280..=304).contains(&self.dot) {
+                self.copy_vertical_position();
+            }
 
-                    // pattern table index stored in byte 1
-                    let pattern_index = secondary_oam[sprite + 1];
-                    // sprites use palettes 4-7, stored in the first two bits of byte 2
-                    let palette = (secondary_oam[sprite + 2] & 0b11) + 4;
+            if self.dot == 257 {
+                if self.scanline < 240 {
+                    self.evaluate_sprites();
+                } else {
+                    // no sprites are drawn on line 0
+                    self.sprites.secondary_oam = [0xFF; 32];
+                    self.sprites.count = 0;
+                    self.sprites.sprite_zero = false;
+                }
+            }
 
-                    let color = self.evalueate_pixel(pattern_index, palette, pattern_x, pattern_y);
+            if (257..=320).contains(&self.dot) {
+                self.oam_addr = 0;
 
-                    // screen x: (sprite x stored in byte 3) + (current pixel)
-                    frame.0[scanline as usize]
-                        [pixel as usize + secondary_oam[sprite + 3] as usize] = color;
+                // each sprite takes 8 dots to fetch, the same as a background tile
+                let slot = (self.dot as usize - 257) / 8;
+                match (self.dot - 257) % 8 {
+                    0 => self.load_sprite(slot),
+                    4 => {
+                        let address = self.sprite_pattern_address(slot);
+                        let pattern = self.memory.read(address);
+                        self.set_sprite_pattern(slot, pattern, false);
+                    }
+                    6 => {
+                        let address = self.sprite_pattern_address(slot) + 8;
+                        let pattern = self.memory.read(address);
+                        self.set_sprite_pattern(slot, pattern, true);
+                    }
+                    _ => (),
                 }
             }
         }
 
-        frame
+        if self.scanline < 240 && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+    }
+
+    fn sprite_height(&self) -> u8 {
+        // 8x16 sprites are selected by ppuctrl bit 5
+        if self.ctrl & 1 << 5 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Copy the first 8 sprites in range of the current line into secondary oam
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let line = self.scanline;
+        let in_range = |y: u8| line.wrapping_sub(y as u16) < height as u16;
+
+        self.sprites.secondary_oam = [0xFF; 32];
+        self.sprites.count = 0;
+        self.sprites.sprite_zero = in_range(self.oam[0]);
+
+        let mut n = 0;
+        while n < 64 && self.sprites.count < 8 {
+            let y = self.oam[n * 4];
+            if in_range(y) {
+                let dst = self.sprites.count * 4;
+                self.sprites.secondary_oam[dst..dst + 4]
+                    .copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+                self.sprites.count += 1;
+            }
+            n += 1;
+        }
+
+        // with secondary oam full, the hardware keeps looking for a ninth sprite to set the
+        // overflow flag, but also increments the byte offset into each entry when it misses,
+        // so it checks tile, attribute and x bytes as if they were y coordinates
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status |= 1 << 5;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    fn load_sprite(&mut self, slot: usize) {
+        let entry = &self.sprites.secondary_oam[slot * 4..slot * 4 + 4];
+        self.sprites.units[slot] = Sprite {
+            y: entry[0],
+            tile: entry[1],
+            attributes: entry[2],
+            x: entry[3],
+            pattern_low: 0,
+            pattern_high: 0,
+        };
+    }
+
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let sprite = &self.sprites.units[slot];
+        let height = self.sprite_height();
+
+        // empty slots still fetch tile $FF
+        let mut row = if slot < self.sprites.count {
+            (self.scanline as u8).wrapping_sub(sprite.y) % height
+        } else {
+            0
+        };
+
+        // vertical flip
+        if sprite.attributes & 1 << 7 != 0 {
+            row = height - 1 - row;
+        }
+
+        let (table, mut tile) = if height == 16 {
+            // 8x16 sprites take their pattern table from bit 0 of the tile index
+            let table = if sprite.tile & 1 != 0 { 0x1000 } else { 0 };
+            (table, sprite.tile & 0xFE)
+        } else {
+            // 8x8 sprite pattern table is selected by ppuctrl bit 3
+            let table = if self.ctrl & 1 << 3 != 0 { 0x1000 } else { 0 };
+            (table, sprite.tile)
+        };
+
+        // the bottom half of an 8x16 sprite is the next tile
+        if row >= 8 {
+            tile = tile.wrapping_add(1);
+            row -= 8;
+        }
+
+        table + tile as u16 * 16 + row as u16
     }
 
-    fn evalueate_pixel(&self, pattern_index: u8, palette: u8, x: u8, y: u8) -> u8 {
-        0x00
+    fn set_sprite_pattern(&mut self, slot: usize, mut pattern: u8, high: bool) {
+        // empty slots are transparent
+        if slot >= self.sprites.count {
+            pattern = 0;
+        }
+
+        let sprite = &mut self.sprites.units[slot];
+
+        // horizontal flip
+        if sprite.attributes & 1 << 6 != 0 {
+            pattern = pattern.reverse_bits();
+        }
+
+        if high {
+            sprite.pattern_high = pattern;
+        } else {
+            sprite.pattern_low = pattern;
+        }
+    }
+
+    /// The pixel value, attributes and slot of the first opaque sprite at `x`, if any
+    fn sprite_pixel(&self, x: u16) -> Option<(u8, u8, usize)> {
+        for (slot, sprite) in self.sprites.units[..self.sprites.count].iter().enumerate() {
+            let offset = x.wrapping_sub(sprite.x as u16);
+            if offset >= 8 {
+                continue;
+            }
+
+            let bit = 7 - offset;
+            let pixel = (sprite.pattern_high >> bit & 1) << 1 | (sprite.pattern_low >> bit & 1);
+            if pixel != 0 {
+                return Some((pixel, sprite.attributes, slot));
+            }
+        }
+
+        None
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            // wrap to the start of the horizontally adjacent nametable
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            // increment fine y
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            // last row of tiles, wrap to the vertically adjacent nametable
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // out of bounds rows wrap without switching nametable
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    fn copy_horizontal_position(&mut self) {
+        // coarse x and horizontal nametable
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn copy_vertical_position(&mut self) {
+        // fine y, coarse y and vertical nametable
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn nametable_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    /// Fetch the 2-bit background palette of the current tile from the attribute table
+    fn fetch_attribute(&mut self) -> u8 {
+        let coarse_x = self.v & 0x001F;
+        let coarse_y = (self.v & 0x03E0) >> 5;
+
+        // each attribute byte covers a 4x4 tile area, at the end of the nametable
+        let address = 0x23C0 | (self.v & 0x0C00) | (coarse_y / 4) << 3 | (coarse_x / 4);
+        let attribute = self.memory.read(address);
+
+        // and holds one palette for each 2x2 tile quadrant
+        let shift = (coarse_y & 0b10) << 1 | (coarse_x & 0b10);
+        (attribute >> shift) & 0b11
+    }
+
+    fn pattern_address(&self) -> u16 {
+        // background pattern table is selected by ppuctrl bit 4
+        let table = if self.ctrl & 1 << 4 != 0 { 0x1000 } else { 0 };
+        let fine_y = (self.v & 0x7000) >> 12;
+
+        table + self.background.next_tile as u16 * 16 + fine_y
+    }
+
+    fn load_background_shifters(&mut self) {
+        let bg = &mut self.background;
+        bg.pattern_low = (bg.pattern_low & 0xFF00) | bg.next_pattern_low as u16;
+        bg.pattern_high = (bg.pattern_high & 0xFF00) | bg.next_pattern_high as u16;
+
+        let palette_low = if bg.next_palette & 0b01 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        let palette_high = if bg.next_palette & 0b10 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        bg.palette_low = (bg.palette_low & 0xFF00) | palette_low;
+        bg.palette_high = (bg.palette_high & 0xFF00) | palette_high;
+    }
+
+    fn shift_background(&mut self) {
+        self.background.pattern_low <<= 1;
+        self.background.pattern_high <<= 1;
+        self.background.palette_low <<= 1;
+        self.background.palette_high <<= 1;
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.dot - 1;
+
+        let mut pixel = 0;
+        let mut palette = 0;
+        // show background, and the leftmost 8 pixels are only drawn if ppumask bit 1 is set
+        if self.mask & 1 << 3 != 0 && (x >= 8 || self.mask & 1 << 1 != 0) {
+            let bg = &self.background;
+            let bit = 15 - self.fine_x;
+
+            pixel = ((bg.pattern_high >> bit) as u8 & 1) << 1 | (bg.pattern_low >> bit) as u8 & 1;
+            palette = ((bg.palette_high >> bit) as u8 & 1) << 1 | (bg.palette_low >> bit) as u8 & 1;
+        }
+
+        // show sprites, and the leftmost 8 pixels are only drawn if ppumask bit 2 is set
+        if self.mask & 1 << 4 != 0 && (x >= 8 || self.mask & 1 << 2 != 0) {
+            if let Some((sprite_pixel, attributes, slot)) = self.sprite_pixel(x) {
+                // sprite 0 hit: an opaque pixel of sprite 0 over an opaque background pixel.
+                // Clipped pixels are never opaque, and it never happens at x = 255
+                if slot == 0 && self.sprites.sprite_zero && pixel != 0 && x != 255 {
+                    self.status |= 1 << 6;
+                }
+
+                // sprites in front of the background (attribute bit 5 clear) or over a
+                // transparent background pixel win, using palettes 4-7
+                if pixel == 0 || attributes & 1 << 5 == 0 {
+                    pixel = sprite_pixel;
+                    palette = (attributes & 0b11) + 4;
+                }
+            }
+        }
+
+        // pixel value 0 is transparent and shows the universal background color
+        let color = if pixel == 0 {
+            self.memory.palette_color(0x3f00)
+        } else {
+            self.memory
+                .palette_color(0x3f00 + palette as u16 * 4 + pixel as u16)
+        };
+        self.frame.0[self.scanline as usize][x as usize] = color;
+    }
+
+    /// Advance `v` after a ppudata access
+    fn increment_vram_addr(&mut self) {
+        let rendering_line = self.scanline < 240 || self.scanline == self.pre_render_line();
+
+        if self.rendering_enabled() && rendering_line {
+            // while rendering, the access glitches both of the rendering increments instead
+            self.increment_coarse_x();
+            self.increment_y();
+        } else {
+            // ppuctrl bit 2 selects incrementing by 32 (going down) instead of 1 (going across)
+            let increment = if self.ctrl & 1 << 2 != 0 { 32 } else { 1 };
+            self.v = (self.v + increment) & 0x7fff;
+        }
     }
 
     pub fn write_register(&mut self, reg: PpuRegister, value: u8) {
         match reg {
-            PpuRegister::PpuCtrl => self.ctrl = value,
+            PpuRegister::PpuCtrl => {
+                self.ctrl = value;
+                // base nametable select
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0b11) << 10);
+                // enabling nmis during vblank raises one immediately
+                self.update_nmi();
+            }
             PpuRegister::PpuMask => self.mask = value,
             PpuRegister::OamAddr => self.oam_addr = value,
             PpuRegister::OamData => {
                 self.oam[self.oam_addr as usize] = value;
-                self.oam_addr += 1;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
             }
-            PpuRegister::PpuScroll => unimplemented!(),
-            PpuRegister::PpuAddr => {
+            PpuRegister::PpuScroll => {
                 if !self.w {
-                    self.vram_addr = 0;
-                    self.vram_addr |= (value as u16) << 8;
+                    // x scroll: coarse x and fine x
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.fine_x = value & 0b111;
                 } else {
-                    self.vram_addr |= value as u16;
+                    // y scroll: coarse y and fine y
+                    self.t = (self.t & !0x73E0)
+                        | (value as u16 >> 3) << 5
+                        | (value as u16 & 0b111) << 12;
                 }
 
                 self.w = !self.w;
-
-                if self.vram_addr > 0x3fff {
-                    self.vram_addr = 0x3fff;
+            }
+            PpuRegister::PpuAddr => {
+                if !self.w {
+                    // high 6 bits, the 15th bit of t is cleared
+                    self.t = (self.t & 0x00FF) | (value as u16 & 0x3F) << 8;
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                    // outside of rendering the ppu leaves v on the address bus
+                    self.memory.address = self.v & 0x3fff;
                 }
+
+                self.w = !self.w;
             }
             PpuRegister::PpuData => {
-                self.memory.write(self.vram_addr, value);
+                self.memory.write(self.v & 0x3fff, value);
+                self.increment_vram_addr();
             }
-            PpuRegister::OamDma => unimplemented!(),
             _ => panic!("Write to invalid PPU register {:?}", reg),
         }
     }
@@ -128,46 +624,126 @@ impl Ppu {
     pub fn read_register(&mut self, reg: PpuRegister) -> u8 {
         match reg {
             PpuRegister::PpuStatus => {
+                if self.scanline == 241 {
+                    match self.dot {
+                        // reading the dot before vblank starts reads it as clear and stops it
+                        // being set this frame
+                        1 => self.suppress_vblank = true,
+                        // reading as it is set still clears it, but the nmi is never raised
+                        2 | 3 => self.nmi_pending = false,
+                        _ => (),
+                    }
+                }
+
+                let status = self.status;
+
                 self.w = false;
-                self.status
+                self.status &= !(1 << 7);
+                self.update_nmi();
+
+                status
             }
             PpuRegister::OamData => self.oam[self.oam_addr as usize],
-            PpuRegister::PpuData => self.memory.read(self.vram_addr),
+            PpuRegister::PpuData => {
+                let addr = self.v & 0x3fff;
+
+                let value = if addr >= 0x3f00 {
+                    // palette reads skip the buffer, which is filled from the nametable
+                    // "underneath" the palette instead
+                    self.read_buffer = self.memory.read(addr - 0x1000);
+                    self.memory.read(addr)
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.memory.read(addr);
+                    value
+                };
+
+                self.increment_vram_addr();
+                value
+            }
             _ => panic!("Read from invalid PPU register {:?}", reg),
         }
     }
 }
 
 struct PpuMemory {
-    character_rom: [u8; 0x2000],
+    cartridge: Rc<RefCell<dyn Mapper>>,
+    // 2 KiB of console ram, plus another 2 KiB on the cartridge for four-screen mirroring
+    nametable_ram: [u8; 0x1000],
     pallete_ram: [u8; 0x0020],
+    // the last address put on the address bus, watched by the cartridge
+    address: u16,
 }
 
 impl PpuMemory {
-    pub fn new(character_rom: [u8; 0x2000]) -> Self {
+    pub fn new(cartridge: Rc<RefCell<dyn Mapper>>) -> Self {
         Self {
-            character_rom,
+            cartridge,
+            nametable_ram: [0; 0x1000],
             pallete_ram: [0; 0x0020],
+            address: 0,
+        }
+    }
+
+    /// Map an address in $2000-$3EFF to an index into nametable ram
+    fn nametable_index(&self, addr: u16) -> usize {
+        // $3000-$3EFF mirrors $2000-$2EFF
+        let addr = (addr - 0x2000) % 0x1000;
+        let nametable = addr / 0x400;
+        let offset = addr % 0x400;
+
+        let physical_nametable = match self.cartridge.borrow().mirroring() {
+            Mirroring::Horizontal => nametable / 2,
+            Mirroring::Vertical => nametable % 2,
+            Mirroring::FourScreen => nametable,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+
+        (physical_nametable * 0x400 + offset) as usize
+    }
+
+    /// Map an address in $3F00-$3FFF to an index into palette ram
+    fn palette_index(&self, addr: u16) -> usize {
+        // $3F20-$3FFF mirrors $3F00-$3F1F
+        let index = addr as usize % 0x20;
+
+        // the sprite palettes' transparent entries $3F10/$3F14/$3F18/$3F1C mirror
+        // the background palettes' entries $3F00/$3F04/$3F08/$3F0C
+        if index & 0x13 == 0x10 {
+            index & 0x0f
+        } else {
+            index
         }
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.address = addr;
         match addr {
-            0x0000..=0x0fff => self.character_rom[addr as usize], // pattern table 0
-            0x1000..=0x1fff => self.character_rom[addr as usize], // pattern table 1
-            // FIXME: Handle nametables correctly
-            0x3f00..=0x3f1f => self.pallete_ram[addr as usize - 0x3f00],
+            0x0000..=0x1fff => self.cartridge.borrow_mut().ppu_read(addr), // pattern tables
+            0x2000..=0x3eff => self.nametable_ram[self.nametable_index(addr)], // nametables
+            0x3f00..=0x3fff => self.pallete_ram[self.palette_index(addr)],
             _ => panic!("Read from invalid PPU memory address {:x}", addr),
         }
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        self.address = addr;
         match addr {
-            0x0000..=0x0fff => (), // pattern table 0; ROM
-            0x1000..=0x1fff => (), // pattern table 1; ROM
-            // FIXME: Handle nametables correctly
-            0x3f00..=0x3f1f => self.pallete_ram[addr as usize - 0x3f00] = value,
+            0x0000..=0x1fff => self.cartridge.borrow_mut().ppu_write(addr, value), // pattern tables
+            0x2000..=0x3eff => self.nametable_ram[self.nametable_index(addr)] = value, // nametables
+            0x3f00..=0x3fff => self.pallete_ram[self.palette_index(addr)] = value,
             _ => panic!("Write to invalid PPU memory address {:x}", addr),
         }
     }
+
+    /// Look up a color while drawing, which doesn't go through the address bus
+    pub fn palette_color(&self, addr: u16) -> u8 {
+        self.pallete_ram[self.palette_index(addr)]
+    }
+
+    /// Let the cartridge see the address bus for this dot
+    pub fn clock(&mut self) {
+        self.cartridge.borrow_mut().ppu_clock(self.address);
+    }
 }